@@ -0,0 +1,113 @@
+//! Minimal JSON-RPC client for peach-buttons.
+//!
+//! peach-buttons exposes a JSON-RPC pubsub interface over websockets, which is not
+//! covered by the HTTP clients in peach-lib, so requests are made directly using tungstenite.
+
+use std::env;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use log::debug;
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::error::ProbeError;
+use crate::jsonrpc;
+
+/// address of peach-buttons if the PEACH_BUTTONS_SERVER environment variable is not set
+pub const DEFAULT_BUTTONS_SERVER: &str = "127.0.0.1:5111";
+
+/// how long to wait for peach-buttons to respond before giving up
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ButtonsClient holds an open websocket connection to peach-buttons
+pub struct ButtonsClient {
+    socket: WebSocket<TcpStream>,
+    next_id: u64,
+}
+
+impl ButtonsClient {
+    /// open a websocket connection to peach-buttons, at the address set by PEACH_BUTTONS_SERVER
+    pub fn connect() -> Result<ButtonsClient, ProbeError> {
+        let address =
+            env::var("PEACH_BUTTONS_SERVER").unwrap_or_else(|_| DEFAULT_BUTTONS_SERVER.to_string());
+        ButtonsClient::connect_to(&address)
+    }
+
+    /// open a websocket connection to peach-buttons at address (host:port)
+    pub fn connect_to(address: &str) -> Result<ButtonsClient, ProbeError> {
+        debug!("connecting to peach-buttons at {}", address);
+        // io errors are returned as websocket errors, since they happen while connecting to the websocket
+        let stream = TcpStream::connect(address).map_err(tungstenite::Error::from)?;
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(tungstenite::Error::from)?;
+        let url = format!("ws://{}", address);
+        let (socket, _response) = tungstenite::client(url.as_str(), stream)
+            .map_err(|e| ProbeError::WebSocketHandshakeError(e.to_string()))?;
+        Ok(ButtonsClient { socket, next_id: 1 })
+    }
+
+    /// send a JSON-RPC request and wait for the response with a matching id,
+    /// ignoring any notifications which arrive in the meantime
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, ProbeError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = jsonrpc::request(id, method, params);
+        self.socket
+            .write_message(Message::Text(request.to_string()))?;
+        loop {
+            let text = match self.socket.read_message()? {
+                Message::Text(text) => text,
+                Message::Close(_) => {
+                    return Err(ProbeError::UnexpectedResponse(
+                        "connection closed by peach-buttons".to_string(),
+                    ))
+                }
+                _ => continue,
+            };
+            let response: Value = serde_json::from_str(&text)?;
+            // notifications have no id, so skip them until the response arrives
+            if response.get("id") != Some(&json!(id)) {
+                debug!("ignoring message from peach-buttons: {}", text);
+                continue;
+            }
+            return jsonrpc::response_result(&response);
+        }
+    }
+
+    /// call the ping method, which returns "success" if peach-buttons is running
+    pub fn ping(&mut self) -> Result<String, ProbeError> {
+        let result = self.call("ping", json!([]))?;
+        match result.as_str() {
+            Some("success") => Ok("success".to_string()),
+            _ => Err(ProbeError::UnexpectedResponse(result.to_string())),
+        }
+    }
+
+    /// subscribe to button press events, returning the id of the new subscription
+    pub fn subscribe_buttons(&mut self) -> Result<Value, ProbeError> {
+        let result = self.call("subscribe_buttons", json!([]))?;
+        // subscription ids are either numbers or strings
+        if result.is_number() || result.is_string() {
+            Ok(result)
+        } else {
+            Err(ProbeError::UnexpectedResponse(result.to_string()))
+        }
+    }
+
+    /// cancel a subscription to button press events
+    pub fn unsubscribe_buttons(&mut self, subscription_id: &Value) -> Result<bool, ProbeError> {
+        let result = self.call("unsubscribe_buttons", json!([subscription_id]))?;
+        match result.as_bool() {
+            Some(true) => Ok(true),
+            _ => Err(ProbeError::UnexpectedResponse(result.to_string())),
+        }
+    }
+
+    /// close the websocket connection
+    pub fn close(mut self) -> Result<(), ProbeError> {
+        self.socket.close(None)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+//! Compatibility matrix of the versions of services which are known to work together.
+//!
+//! The matrix is given in the `compatibility` table of the config file, for example:
+//!
+//! ```toml
+//! [compatibility.versions]
+//! peach-network = ">=0.2.4, <0.3"
+//! peach-web = "^0.3"
+//!
+//! # peach-web 0.3 uses the new peach-network api
+//! [[compatibility.constraint]]
+//! when = { service = "peach-web", versions = ">=0.3.0" }
+//! requires = { service = "peach-network", versions = ">=0.2.4" }
+//! ```
+//!
+//! A version range is a list of comparisons separated by commas, all of which must match. Comparisons use
+//! `=`, `>`, `>=`, `<` and `<=`, or `^` and `~` as in semver, and a version without an operator is treated
+//! like `^`. Unless a comparison gives an epoch or a debian revision, those of the installed version are ignored.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde_derive::Deserialize;
+
+use crate::dpkg::DebianVersion;
+use crate::probe::ProbeResult;
+
+/// CompatibilityMatrix lists the versions of each service which are known to work,
+/// and constraints on the versions of services which depend on the versions of other services
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CompatibilityMatrix {
+    // range of versions of each service which are known to work, keyed by service name
+    #[serde(default)]
+    pub versions: HashMap<String, VersionRange>,
+    // constraints between the versions of different services
+    #[serde(default, rename = "constraint")]
+    pub constraints: Vec<Constraint>,
+}
+
+/// Constraint requires a service to have a version in a range, when another service has a version in a range
+#[derive(Debug, Clone, Deserialize)]
+pub struct Constraint {
+    pub when: ServiceVersions,
+    pub requires: ServiceVersions,
+}
+
+/// ServiceVersions is a range of versions of a particular service
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceVersions {
+    pub service: String,
+    pub versions: VersionRange,
+}
+
+/// VersionRange is a set of versions, given as a list of comparisons which must all match
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct VersionRange {
+    // the range as it was written, which is used in reports
+    text: String,
+    comparisons: Vec<(Operator, DebianVersion)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl TryFrom<String> for VersionRange {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<VersionRange, String> {
+        let invalid = |reason: String| format!("invalid version range {}: {}", text, reason);
+        let mut comparisons = Vec::new();
+        for comparison in text.split(',').map(str::trim) {
+            if comparison == "*" {
+                continue;
+            }
+            // longer operators are matched first, so that >= is not read as >
+            let (operator, version) = [">=", "<=", ">", "<", "=", "^", "~"]
+                .iter()
+                .find(|operator| comparison.starts_with(*operator))
+                .map(|operator| (*operator, comparison[operator.len()..].trim()))
+                .unwrap_or(("^", comparison));
+            let version: DebianVersion = version.parse().map_err(|e| invalid(format!("{}", e)))?;
+            match operator {
+                ">=" => comparisons.push((Operator::Ge, version)),
+                "<=" => comparisons.push((Operator::Le, version)),
+                ">" => comparisons.push((Operator::Gt, version)),
+                "<" => comparisons.push((Operator::Lt, version)),
+                "=" => comparisons.push((Operator::Eq, version)),
+                _ => {
+                    let upper =
+                        next_breaking_version(&version, operator == "^").map_err(invalid)?;
+                    comparisons.push((Operator::Ge, version));
+                    comparisons.push((Operator::Lt, upper));
+                }
+            }
+        }
+        Ok(VersionRange { text, comparisons })
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl VersionRange {
+    /// returns true if the version is in this range
+    pub fn matches(&self, version: &DebianVersion) -> bool {
+        self.comparisons
+            .iter()
+            .all(|(operator, bound)| operator.matches(compare(version, bound)))
+    }
+
+    /// returns true if the version is not in this range because it is older than the range allows
+    pub fn is_outdated(&self, version: &DebianVersion) -> bool {
+        self.comparisons.iter().any(|(operator, bound)| {
+            let ordering = compare(version, bound);
+            !operator.matches(ordering) && ordering == Ordering::Less
+        })
+    }
+}
+
+impl Operator {
+    /// returns true if a version which compares to the bound with the given ordering matches this operator
+    fn matches(self, ordering: Ordering) -> bool {
+        match self {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Ge => ordering != Ordering::Less,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+        }
+    }
+}
+
+/// helper function which compares a version with a bound, ignoring the epoch and debian revision
+/// of the version if the bound does not give them
+fn compare(version: &DebianVersion, bound: &DebianVersion) -> Ordering {
+    let version = DebianVersion {
+        epoch: if bound.epoch == 0 { 0 } else { version.epoch },
+        upstream: version.upstream.clone(),
+        revision: if bound.revision.is_empty() {
+            String::new()
+        } else {
+            version.revision.clone()
+        },
+    };
+    version.cmp(bound)
+}
+
+/// helper function which gets the first version which is not compatible with the given version:
+/// the next major version for ^, or the next major version below 1.0, and the next minor version for ~
+fn next_breaking_version(version: &DebianVersion, caret: bool) -> Result<DebianVersion, String> {
+    let parts = version
+        .upstream
+        .split('.')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| format!("{} is not a semver version", version.upstream))?;
+    let index = if caret {
+        parts
+            .iter()
+            .position(|part| *part != 0)
+            .unwrap_or(parts.len() - 1)
+    } else {
+        1.min(parts.len() - 1)
+    };
+    let mut upper: Vec<String> = parts[..index].iter().map(u64::to_string).collect();
+    upper.push((parts[index] + 1).to_string());
+    Ok(DebianVersion {
+        epoch: version.epoch,
+        upstream: upper.join("."),
+        revision: String::new(),
+    })
+}
+
+/// check the detected versions of the probed services against the matrix, recording the versions which
+/// are outdated or incompatible with the versions of other services in the results of those services
+/// services whose version is not known are not checked
+pub fn check(matrix: &CompatibilityMatrix, results: &mut [ProbeResult]) {
+    let versions: HashMap<String, DebianVersion> = results
+        .iter()
+        .filter_map(|result| Some((result.microservice.clone(), result.version.parse().ok()?)))
+        .collect();
+
+    for result in results.iter_mut() {
+        result.incompatibilities.clear();
+        let version = match versions.get(&result.microservice) {
+            Some(version) => version,
+            None => continue,
+        };
+        let problem = |range: &VersionRange| {
+            if range.is_outdated(version) {
+                "outdated"
+            } else {
+                "incompatible"
+            }
+        };
+
+        if let Some(range) = matrix.versions.get(&result.microservice) {
+            if !range.matches(version) {
+                result.incompatibilities.push(format!(
+                    "version {} is {}, known to work with {}",
+                    version,
+                    problem(range),
+                    range
+                ));
+            }
+        }
+
+        for constraint in &matrix.constraints {
+            let required = &constraint.requires;
+            if required.service != result.microservice || required.versions.matches(version) {
+                continue;
+            }
+            let when = &constraint.when;
+            if let Some(when_version) = versions.get(&when.service) {
+                if when.versions.matches(when_version) {
+                    result.incompatibilities.push(format!(
+                        "version {} is {}, {} {} requires {}",
+                        version,
+                        problem(&required.versions),
+                        when.service,
+                        when_version,
+                        required.versions
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(text: &str) -> VersionRange {
+        VersionRange::try_from(text.to_string()).unwrap()
+    }
+
+    fn version(version: &str) -> DebianVersion {
+        version.parse().unwrap()
+    }
+
+    /// helper function which gets the result of a service with the given installed version
+    fn result(microservice: &str, version: &str) -> ProbeResult {
+        let mut result = ProbeResult::new(microservice);
+        result.version = version.to_string();
+        result
+    }
+
+    #[test]
+    fn caret_allows_changes_below_the_first_non_zero_part() {
+        let caret = range("^0.3");
+        assert!(caret.matches(&version("0.3.0")));
+        assert!(caret.matches(&version("0.3.9")));
+        assert!(!caret.matches(&version("0.4.0")));
+        assert!(!caret.matches(&version("0.2.9")));
+
+        let patch = range("^0.0.3");
+        assert!(patch.matches(&version("0.0.3")));
+        assert!(!patch.matches(&version("0.0.4")));
+
+        // a version without an operator is treated like ^
+        assert!(range("1.2").matches(&version("1.9.0")));
+        assert!(!range("1.2").matches(&version("2.0.0")));
+    }
+
+    #[test]
+    fn tilde_allows_changes_below_the_minor_version() {
+        let major = range("~1");
+        assert!(major.matches(&version("1.9.0")));
+        assert!(!major.matches(&version("2.0.0")));
+
+        let minor = range("~1.2");
+        assert!(minor.matches(&version("1.2.7")));
+        assert!(!minor.matches(&version("1.3.0")));
+    }
+
+    #[test]
+    fn reads_longer_operators_first() {
+        assert!(range(">=0.2.4").matches(&version("0.2.4")));
+        assert!(!range(">0.2.4").matches(&version("0.2.4")));
+        assert!(range("<=0.2.4").matches(&version("0.2.4")));
+        assert!(!range("<0.2.4").matches(&version("0.2.4")));
+        assert!(range(">=0.2.4, <0.3").matches(&version("0.2.9")));
+        assert!(range("*").matches(&version("9.9.9")));
+        assert!(VersionRange::try_from("^a.b".to_string()).is_err());
+    }
+
+    #[test]
+    fn ignores_revision_and_epoch_unless_given() {
+        assert!(range("=0.2.4").matches(&version("0.2.4-3")));
+        assert!(range("^0.2").matches(&version("1:0.2.4-1")));
+        assert!(!range("=0.2.4-1").matches(&version("0.2.4-3")));
+        assert!(range(">0.2.4-1").matches(&version("0.2.4-3")));
+        assert!(!range(">=1:0.2").matches(&version("0.2.4")));
+    }
+
+    #[test]
+    fn classifies_outdated_and_incompatible_versions() {
+        let known = range(">=0.2.4, <0.3");
+        assert!(known.is_outdated(&version("0.2.3")));
+        assert!(!known.is_outdated(&version("0.3.0")));
+        assert!(!known.is_outdated(&version("0.2.5")));
+    }
+
+    #[test]
+    fn checks_versions_and_constraints() {
+        let matrix: CompatibilityMatrix = toml::from_str(
+            r#"
+            [versions]
+            peach-network = ">=0.2.4, <0.3"
+            peach-oled = "^0.1"
+
+            [[constraint]]
+            when = { service = "peach-web", versions = ">=0.3.0" }
+            requires = { service = "peach-oled", versions = ">=0.1.5" }
+            "#,
+        )
+        .unwrap();
+        let mut results = vec![
+            result("peach-network", "0.3.1-1"),
+            result("peach-oled", "0.1.2"),
+            result("peach-web", "0.3.1"),
+            result("peach-stats", "Unknown"),
+        ];
+        check(&matrix, &mut results);
+
+        assert_eq!(
+            results[0].incompatibilities,
+            vec!["version 0.3.1-1 is incompatible, known to work with >=0.2.4, <0.3"]
+        );
+        assert_eq!(
+            results[1].incompatibilities,
+            vec!["version 0.1.2 is outdated, peach-web 0.3.1 requires >=0.1.5"]
+        );
+        assert!(results[2].incompatibilities.is_empty());
+        assert!(results[3].incompatibilities.is_empty());
+
+        // the constraint only applies when peach-web is in its range
+        results[2].version = "0.2.0".to_string();
+        check(&matrix, &mut results);
+        assert!(results[1].incompatibilities.is_empty());
+    }
+}
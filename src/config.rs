@@ -0,0 +1,421 @@
+//! Registry of the services which peach-probe knows how to probe.
+//!
+//! By default the registry contains the built-in PeachCloud microservices. A TOML file can be
+//! used to override their settings or add new services, for example:
+//!
+//! ```toml
+//! [[service]]
+//! name = "peach-stats"
+//! address = "127.0.0.1:6113"
+//!
+//! [service.latency.available_networks]
+//! warn_ms = 10000
+//! fail_ms = 30000
+//! timeout_ms = 60000
+//!
+//! [[service]]
+//! name = "peach-dyndns"
+//! address = "127.0.0.1:5114"
+//! contract = "/etc/peach-probe/contracts/peach-dyndns.json"
+//!
+//! [[service]]
+//! name = "nginx"
+//! checks = ["status"]
+//! required = false
+//!
+//! [compatibility.versions]
+//! peach-network = ">=0.2.4, <0.3"
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+
+use crate::compat::CompatibilityMatrix;
+use crate::error::ProbeError;
+use crate::Microservice;
+
+/// path of the config file which is loaded if no other path is given on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/peach-probe/services.toml";
+
+/// Check is a kind of check which can be run against a service
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Check {
+    // check whether the systemd unit is running
+    Status,
+    // look up the installed version of the package
+    Version,
+    // call the endpoints of the service, using the built-in probe given by `endpoints`
+    Endpoints,
+}
+
+/// LatencyThreshold sets how long an endpoint may take before it is reported as slow,
+/// how long it may take before it is reported as failing, and how long to wait for it
+/// before giving up and reporting it as timed out
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct LatencyThreshold {
+    #[serde(default)]
+    pub warn_ms: Option<u64>,
+    #[serde(default)]
+    pub fail_ms: Option<u64>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+impl LatencyThreshold {
+    /// fill in any thresholds which are not set from the given defaults
+    pub fn or(self, defaults: LatencyThreshold) -> LatencyThreshold {
+        LatencyThreshold {
+            warn_ms: self.warn_ms.or(defaults.warn_ms),
+            fail_ms: self.fail_ms.or(defaults.fail_ms),
+            timeout_ms: self.timeout_ms.or(defaults.timeout_ms),
+        }
+    }
+
+    /// returns true if an endpoint which took this long should be reported as slow
+    pub fn is_warning(&self, latency: Duration) -> bool {
+        matches!(self.warn_ms, Some(warn_ms) if latency > Duration::from_millis(warn_ms))
+    }
+
+    /// returns true if an endpoint which took this long should be reported as failing
+    pub fn is_failure(&self, latency: Duration) -> bool {
+        matches!(self.fail_ms, Some(fail_ms) if latency > Duration::from_millis(fail_ms))
+    }
+}
+
+/// ServiceConfig describes a single service in the registry
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceConfig {
+    // name of the debian package, which is also used as the name of the service in reports
+    pub name: String,
+    // name of the systemd unit, if it is different from the package name
+    #[serde(default)]
+    pub unit: Option<String>,
+    // address (host:port) of the service's api, if it is different from the default
+    #[serde(default)]
+    pub address: Option<String>,
+    // name of the built-in probe used to check endpoints, e.g. "peach-stats"
+    #[serde(default)]
+    pub endpoints: Option<String>,
+    // path of a contract file used to check endpoints, instead of the built-in probe,
+    // which if relative is relative to the directory of the config file
+    #[serde(default)]
+    pub contract: Option<PathBuf>,
+    // checks which are run against the service
+    #[serde(default = "default_checks")]
+    pub checks: Vec<Check>,
+    // if false, the service being offline or failing does not affect the exit code
+    #[serde(default = "default_true")]
+    pub required: bool,
+    // latency thresholds for particular endpoints, overriding the defaults given on the command line
+    #[serde(default)]
+    pub latency: HashMap<String, LatencyThreshold>,
+    // total time allowed for probing the endpoints of this service, overriding the default given on the command line
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    // name of a JSON-RPC method which returns the version of the running service, if it has one
+    #[serde(default)]
+    pub version_method: Option<String>,
+    // if true, this service has side effects and is never probed at the same time as other services
+    #[serde(default)]
+    pub exclusive: Option<bool>,
+}
+
+impl ServiceConfig {
+    /// build the default config for one of the built-in microservices
+    /// these use their hand-written probes rather than contracts, since those probes also check
+    /// invariants of results, and restore the network state after probing peach-network
+    pub fn builtin(service: &Microservice) -> ServiceConfig {
+        let name = Microservice::get_package_name(service);
+        ServiceConfig {
+            endpoints: Some(name.clone()),
+            name,
+            unit: None,
+            address: None,
+            contract: None,
+            checks: default_checks(),
+            required: true,
+            latency: HashMap::new(),
+            timeout_ms: None,
+            version_method: None,
+            exclusive: None,
+        }
+    }
+
+    /// get the name of the systemd unit for this service
+    pub fn unit_name(&self) -> &str {
+        self.unit.as_deref().unwrap_or(&self.name)
+    }
+
+    /// get the built-in probe used to check the endpoints of this service, if any
+    pub fn builtin_probe(&self) -> Option<Microservice> {
+        self.endpoints
+            .as_ref()
+            .and_then(|name| Microservice::from_str(&name.replace('-', "_")).ok())
+    }
+
+    /// get the address of the service's api, falling back to the address used by peach-lib
+    /// for built-in microservices
+    pub fn api_address(&self) -> Option<String> {
+        self.address.clone().or_else(|| {
+            let service = self.builtin_probe()?;
+            let var = Microservice::get_address_var(&service)?;
+            Some(
+                std::env::var(var)
+                    .unwrap_or_else(|_| Microservice::get_default_address(&service).to_string()),
+            )
+        })
+    }
+
+    /// returns true if this service must not be probed at the same time as other services
+    /// by default, this is the case for peach-network, which switches between ap and client mode,
+    /// and peach-oled, which clears and powers off the display
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive.unwrap_or_else(|| {
+            matches!(
+                self.builtin_probe(),
+                Some(Microservice::Peach_Network) | Some(Microservice::Peach_Oled)
+            ) && self.runs(Check::Endpoints)
+        })
+    }
+
+    /// returns true if the given check should be run against this service
+    pub fn runs(&self, check: Check) -> bool {
+        self.checks.contains(&check)
+    }
+}
+
+/// ProbeConfig is the registry of all services which can be probed
+#[derive(Debug, Deserialize)]
+pub struct ProbeConfig {
+    // if true, the built-in microservices are included in the registry
+    #[serde(default = "default_true")]
+    pub builtin_services: bool,
+    // services in the registry, in the order they are probed by default
+    #[serde(default, rename = "service")]
+    pub services: Vec<ServiceConfig>,
+    // versions of services which are known to work together
+    #[serde(default)]
+    pub compatibility: CompatibilityMatrix,
+}
+
+impl Default for ProbeConfig {
+    /// the default registry contains all built-in microservices
+    fn default() -> ProbeConfig {
+        let services = [
+            Microservice::Peach_Network,
+            Microservice::Peach_Oled,
+            Microservice::Peach_Stats,
+            Microservice::Peach_Web,
+            Microservice::Peach_Buttons,
+            Microservice::Peach_Menu,
+            Microservice::Peach_Monitor,
+        ]
+        .iter()
+        .map(ServiceConfig::builtin)
+        .collect();
+        ProbeConfig {
+            builtin_services: true,
+            services,
+            compatibility: CompatibilityMatrix::default(),
+        }
+    }
+}
+
+impl ProbeConfig {
+    /// load the registry from a TOML file, merging it with the built-in microservices
+    /// services in the file replace built-in services with the same name
+    pub fn load(path: &Path) -> Result<ProbeConfig, ProbeError> {
+        let contents = fs::read_to_string(path)?;
+        let file_config: ProbeConfig = toml::from_str(&contents)?;
+
+        let mut config = if file_config.builtin_services {
+            ProbeConfig::default()
+        } else {
+            ProbeConfig {
+                builtin_services: false,
+                services: Vec::new(),
+                compatibility: CompatibilityMatrix::default(),
+            }
+        };
+        config.compatibility = file_config.compatibility;
+        for mut service in file_config.services {
+            // relative contract paths are relative to the directory of the config file
+            if let (Some(contract), Some(dir)) = (&service.contract, path.parent()) {
+                service.contract = Some(dir.join(contract));
+            }
+            // services named after a built-in microservice use its probe unless told otherwise
+            if service.endpoints.is_none()
+                && Microservice::from_str(&service.name.replace('-', "_")).is_ok()
+            {
+                service.endpoints = Some(service.name.clone());
+            }
+            if let Some(endpoints) = &service.endpoints {
+                if service.builtin_probe().is_none() {
+                    return Err(ProbeError::ConfigError(format!(
+                        "unknown endpoints probe {} for service {}",
+                        endpoints, service.name
+                    )));
+                }
+            }
+            match config.services.iter_mut().find(|s| s.name == service.name) {
+                Some(existing) => *existing = service,
+                None => config.services.push(service),
+            }
+        }
+        Ok(config)
+    }
+
+    /// find a service in the registry by name, ignoring case and accepting underscores in place of hyphens
+    pub fn find(&self, name: &str) -> Option<&ServiceConfig> {
+        let normalize = |name: &str| name.to_lowercase().replace('_', "-");
+        let name = normalize(name);
+        self.services
+            .iter()
+            .find(|service| normalize(&service.name) == name)
+    }
+}
+
+/// by default, all checks are run against a service
+fn default_checks() -> Vec<Check> {
+    vec![Check::Status, Check::Version, Check::Endpoints]
+}
+
+/// helper function used by serde for fields which default to true
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// helper function which loads a config file with the given contents from a temporary directory
+    fn load(name: &str, contents: &str) -> Result<ProbeConfig, ProbeError> {
+        let dir = std::env::temp_dir().join(format!(
+            "peach-probe-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("services.toml");
+        fs::write(&path, contents).unwrap();
+        let config = ProbeConfig::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        config
+    }
+
+    #[test]
+    fn empty_file_loads_builtin_services() {
+        let config = load("empty", "").unwrap();
+        let names: Vec<&str> = config.services.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "peach-network",
+                "peach-oled",
+                "peach-stats",
+                "peach-web",
+                "peach-buttons",
+                "peach-menu",
+                "peach-monitor"
+            ]
+        );
+    }
+
+    #[test]
+    fn services_replace_builtin_services_with_the_same_name() {
+        let config = load(
+            "replace",
+            "[[service]]\nname = \"peach-stats\"\naddress = \"127.0.0.1:6113\"\n\n[service.latency.cpu_stats_percent]\nwarn_ms = 100\n",
+        )
+        .unwrap();
+        assert_eq!(config.services.len(), 7);
+        let stats = config.find("peach_stats").unwrap();
+        assert_eq!(stats.address.as_deref(), Some("127.0.0.1:6113"));
+        // services named after a built-in microservice keep its probe and checks
+        assert_eq!(stats.builtin_probe(), Some(Microservice::Peach_Stats));
+        assert!(stats.runs(Check::Endpoints));
+        assert_eq!(stats.latency["cpu_stats_percent"].warn_ms, Some(100));
+        assert_eq!(stats.api_address().as_deref(), Some("127.0.0.1:6113"));
+    }
+
+    #[test]
+    fn new_services_are_added_after_builtin_services() {
+        let config = load(
+            "add",
+            "[[service]]\nname = \"nginx\"\nchecks = [\"status\"]\nrequired = false\n",
+        )
+        .unwrap();
+        assert_eq!(config.services.len(), 8);
+        let nginx = &config.services[7];
+        assert_eq!(nginx.name, "nginx");
+        assert!(!nginx.required);
+        assert!(nginx.runs(Check::Status));
+        assert!(!nginx.runs(Check::Endpoints));
+        assert_eq!(nginx.builtin_probe(), None);
+        assert!(!nginx.is_exclusive());
+    }
+
+    #[test]
+    fn builtin_services_can_be_left_out() {
+        let config = load(
+            "no-builtin",
+            "builtin_services = false\n\n[[service]]\nname = \"peach-oled\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.services.len(), 1);
+        assert_eq!(
+            config.services[0].builtin_probe(),
+            Some(Microservice::Peach_Oled)
+        );
+        assert!(config.services[0].is_exclusive());
+    }
+
+    #[test]
+    fn contract_paths_are_relative_to_the_config_file() {
+        let config = load(
+            "contract",
+            "[[service]]\nname = \"peach-dyndns\"\naddress = \"127.0.0.1:5114\"\ncontract = \"contracts/peach-dyndns.json\"\n\n[[service]]\nname = \"peach-stats\"\ncontract = \"/usr/share/peach-probe/contracts/peach-stats.json\"\n",
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "peach-probe-config-contract-{}",
+            std::process::id()
+        ));
+        assert_eq!(
+            config.find("peach-dyndns").unwrap().contract,
+            Some(dir.join("contracts/peach-dyndns.json"))
+        );
+        assert_eq!(
+            config.find("peach-stats").unwrap().contract,
+            Some(PathBuf::from(
+                "/usr/share/peach-probe/contracts/peach-stats.json"
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_probes_and_invalid_toml() {
+        assert!(matches!(
+            load(
+                "unknown",
+                "[[service]]\nname = \"nginx\"\nendpoints = \"nginx\"\n"
+            ),
+            Err(ProbeError::ConfigError(_))
+        ));
+        assert!(matches!(
+            load("invalid", "[[service]\nname = "),
+            Err(ProbeError::ConfigError(_))
+        ));
+        assert!(matches!(
+            load("missing-name", "[[service]]\naddress = \"127.0.0.1:1\"\n"),
+            Err(ProbeError::ConfigError(_))
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Declarative contracts for JSON-RPC microservices.
+//!
+//! A contract is a json file listing the methods of a microservice, the params to call them with,
+//! and either the shape of the expected result (see shape.rs) or the expected JSON-RPC error code:
+//!
+//! ```json
+//! {
+//!     "endpoints": [
+//!         { "method": "ping", "expect": { "result": "success" } },
+//!         { "method": "load_average", "expect": { "result": { "one": "number", "five": "number" } } },
+//!         { "name": "connect", "method": "connect", "params": { "id": "x", "iface": "wlan0" },
+//!           "expect": { "error_code": -32027 }, "mutating": true }
+//!     ]
+//! }
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::ProbeError;
+use crate::jsonrpc;
+use crate::shape;
+
+/// Contract lists the endpoints of a microservice which should be probed
+#[derive(Debug, Deserialize)]
+pub struct Contract {
+    pub endpoints: Vec<EndpointContract>,
+}
+
+/// EndpointContract describes how to call a single JSON-RPC method and what it should return
+#[derive(Debug, Clone, Deserialize)]
+pub struct EndpointContract {
+    // name of the endpoint in reports, if it is different from the method name
+    #[serde(default)]
+    pub name: Option<String>,
+    // name of the JSON-RPC method to call
+    pub method: String,
+    // params to call the method with, which default to an empty array
+    #[serde(default = "empty_params")]
+    pub params: Value,
+    // what the method is expected to return
+    pub expect: Expectation,
+    // if true, calling the method changes the state of the microservice, so it is skipped in read-only mode
+    #[serde(default)]
+    pub mutating: bool,
+}
+
+/// Expectation is either the shape of a successful result, or a JSON-RPC error code
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expectation {
+    Result(Value),
+    ErrorCode(i64),
+}
+
+impl Contract {
+    /// load a contract from a json file
+    pub fn load(path: &Path) -> Result<Contract, ProbeError> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|err| {
+            ProbeError::ConfigError(format!("invalid contract {}: {}", path.display(), err))
+        })
+    }
+}
+
+impl EndpointContract {
+    /// get the name of this endpoint as shown in reports
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.method)
+    }
+
+    /// call the method on the JSON-RPC server at address, and check the response meets the contract
+    pub fn run(&self, address: &str) -> Result<(), ProbeError> {
+        self.check(jsonrpc::call(address, &self.method, self.params.clone()))
+    }
+
+    /// check that the response of the method meets the contract
+    pub fn check(&self, response: Result<Value, ProbeError>) -> Result<(), ProbeError> {
+        match (&self.expect, response) {
+            (Expectation::Result(expected_shape), Ok(value)) => {
+                shape::check(&value, expected_shape).map_err(ProbeError::InvalidResponse)
+            }
+            (Expectation::ErrorCode(expected_code), Ok(value)) => {
+                Err(ProbeError::UnexpectedResponse(format!(
+                    "expected error with code {}, but returned successfully with {}",
+                    expected_code, value
+                )))
+            }
+            (Expectation::ErrorCode(expected_code), Err(ProbeError::JsonRpcError { code, .. }))
+                if code == *expected_code =>
+            {
+                Ok(())
+            }
+            (_, Err(err)) => Err(err),
+        }
+    }
+}
+
+/// by default, methods are called without params
+fn empty_params() -> Value {
+    json!([])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// load the peach-stats contract shipped in contracts/
+    fn peach_stats_contract() -> Contract {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("contracts/peach-stats.json");
+        Contract::load(&path).unwrap()
+    }
+
+    /// find an endpoint of a contract by name
+    fn endpoint<'a>(contract: &'a Contract, name: &str) -> &'a EndpointContract {
+        contract
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.name() == name)
+            .unwrap()
+    }
+
+    /// helper function which builds a JSON-RPC error response
+    fn jsonrpc_error(code: i64) -> ProbeError {
+        ProbeError::JsonRpcError {
+            code,
+            message: "error".to_string(),
+        }
+    }
+
+    #[test]
+    fn shipped_contract_accepts_canned_responses() {
+        let contract = peach_stats_contract();
+        let responses = [
+            ("ping", json!("success")),
+            (
+                "cpu_stats_percent",
+                json!("{\"user\":4.2,\"system\":1.3,\"idle\":94.5}"),
+            ),
+            ("disk_usage", json!("[]")),
+            (
+                "load_average",
+                json!("{\"one\":0.1,\"five\":0.2,\"fifteen\":0.3}"),
+            ),
+            (
+                "mem_stats",
+                json!("{\"total\":1024,\"free\":512,\"used\":512}"),
+            ),
+            ("uptime", json!("3600")),
+        ];
+        for (name, response) in responses.iter() {
+            let result = endpoint(&contract, name).check(Ok(response.clone()));
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+        }
+        let unknown = endpoint(&contract, "unknown_method");
+        assert!(unknown.check(Err(jsonrpc_error(-32601))).is_ok());
+    }
+
+    #[test]
+    fn rejects_responses_which_break_the_contract() {
+        let contract = peach_stats_contract();
+        assert!(matches!(
+            endpoint(&contract, "ping").check(Ok(json!("failure"))),
+            Err(ProbeError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            endpoint(&contract, "uptime").check(Ok(json!(3600))),
+            Err(ProbeError::InvalidResponse(_))
+        ));
+        let unknown = endpoint(&contract, "unknown_method");
+        assert!(matches!(
+            unknown.check(Ok(json!("success"))),
+            Err(ProbeError::UnexpectedResponse(_))
+        ));
+        assert!(matches!(
+            unknown.check(Err(jsonrpc_error(-32600))),
+            Err(ProbeError::JsonRpcError { code: -32600, .. })
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+//! Detection of services which systemd keeps restarting.
+//!
+//! A unit which crashes and is restarted by systemd can look running whenever it is probed, so the number of
+//! times each unit was restarted within a recent window is counted, from the restart jobs systemd logged to
+//! the journal and from the increase in its NRestarts property over previous runs in watch and export mode.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use crate::probe::ProbeResult;
+use crate::systemd;
+
+/// CrashLoopDetector flags services which were restarted more than max_restarts times within the window
+#[derive(Debug, Clone)]
+pub struct CrashLoopDetector {
+    // number of restarts within the window above which a service is crash-looping
+    pub max_restarts: u32,
+    // how far back restarts are counted
+    pub window: Duration,
+    // NRestarts of each unit seen by previous runs within the window, oldest first, as (unix time, restarts)
+    samples: HashMap<String, VecDeque<(u64, u32)>>,
+}
+
+impl CrashLoopDetector {
+    pub fn new(max_restarts: u32, window: Duration) -> CrashLoopDetector {
+        CrashLoopDetector {
+            max_restarts,
+            window,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// count the recent restarts of every service whose status was checked, flagging those which are crash-looping
+    pub fn check(&mut self, results: &mut [ProbeResult]) {
+        let now = SystemTime::now();
+        let now_secs = now
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        // a window longer than the time since the unix epoch counts every restart in the journal
+        let since = now.checked_sub(self.window).unwrap_or(UNIX_EPOCH);
+
+        for result in results.iter_mut() {
+            let restarts = match &result.unit_status {
+                Some(unit_status) => unit_status.restarts,
+                None => continue,
+            };
+
+            // restarts logged to the journal within the window
+            let logged = match systemd::restarts_since(&result.unit, since) {
+                Ok(logged) => logged,
+                Err(e) => {
+                    info!(
+                        "error reading restarts of {} from journal: {}",
+                        result.unit, e
+                    );
+                    0
+                }
+            };
+
+            // increase in NRestarts since the oldest run within the window
+            let counted = match restarts {
+                Some(restarts) => self.restarts_within_window(&result.unit, restarts, now_secs),
+                None => 0,
+            };
+
+            let recent_restarts = logged.max(counted);
+            result.recent_restarts = Some(recent_restarts);
+            result.crash_looping = recent_restarts > self.max_restarts;
+        }
+    }
+
+    /// helper function which records the NRestarts of a unit seen at now_secs,
+    /// returning its increase since the oldest run within the window
+    fn restarts_within_window(&mut self, unit: &str, restarts: u32, now_secs: u64) -> u32 {
+        let window_start = now_secs.saturating_sub(self.window.as_secs());
+        let samples = self.samples.entry(unit.to_string()).or_default();
+        // NRestarts is reset when the unit is started manually
+        if matches!(samples.back(), Some((_, last)) if *last > restarts) {
+            samples.clear();
+        }
+        samples.push_back((now_secs, restarts));
+        while matches!(samples.front(), Some((time, _)) if *time < window_start) {
+            samples.pop_front();
+        }
+        samples
+            .front()
+            .map(|(_, oldest)| restarts - oldest)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// helper function which builds a detector with a window of 600 seconds
+    fn detector() -> CrashLoopDetector {
+        CrashLoopDetector::new(3, Duration::from_secs(600))
+    }
+
+    #[test]
+    fn counts_increase_in_restarts_within_window() {
+        let mut detector = detector();
+        assert_eq!(detector.restarts_within_window("peach-web", 2, 1000), 0);
+        assert_eq!(detector.restarts_within_window("peach-web", 4, 1060), 2);
+        assert_eq!(detector.restarts_within_window("peach-web", 7, 1120), 5);
+        // other units are counted separately
+        assert_eq!(detector.restarts_within_window("peach-oled", 9, 1120), 0);
+    }
+
+    #[test]
+    fn forgets_restarts_before_window() {
+        let mut detector = detector();
+        detector.restarts_within_window("peach-web", 2, 1000);
+        detector.restarts_within_window("peach-web", 4, 1300);
+        // the run at 1000 is now outside the window, so only the increase since 1300 is counted
+        assert_eq!(detector.restarts_within_window("peach-web", 5, 1700), 1);
+        assert_eq!(detector.restarts_within_window("peach-web", 5, 2400), 0);
+    }
+
+    #[test]
+    fn starts_counting_again_when_restarts_are_reset() {
+        let mut detector = detector();
+        detector.restarts_within_window("peach-web", 6, 1000);
+        assert_eq!(detector.restarts_within_window("peach-web", 0, 1060), 0);
+        assert_eq!(detector.restarts_within_window("peach-web", 3, 1120), 3);
+    }
+}
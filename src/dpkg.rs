@@ -0,0 +1,391 @@
+//! Installed package versions, read from the dpkg database.
+//!
+//! The dpkg status file lists every package dpkg knows about as a paragraph of fields, for example:
+//!
+//! ```text
+//! Package: peach-network
+//! Status: install ok installed
+//! Architecture: arm64
+//! Version: 0.2.4-1
+//! ```
+//!
+//! This works the same on any suite and architecture, unlike parsing the output of apt.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde_derive::Serialize;
+
+use crate::error::ProbeError;
+
+/// get the version of a package installed according to the dpkg status file at status_path
+pub fn installed_version(status_path: &Path, package: &str) -> Result<DebianVersion, ProbeError> {
+    let status = fs::read_to_string(status_path)?;
+    for paragraph in status.split("\n\n") {
+        let field = |name: &str| {
+            paragraph.lines().find_map(|line| {
+                let (key, value) = split_field(line)?;
+                if key.eq_ignore_ascii_case(name) {
+                    Some(value)
+                } else {
+                    None
+                }
+            })
+        };
+        if field("Package") != Some(package) {
+            continue;
+        }
+        // the status is "want flag state", e.g. "install ok installed"
+        let status: Vec<&str> = field("Status").unwrap_or_default().split(' ').collect();
+        match status.as_slice() {
+            [_, "ok", "installed"]
+            | [_, "ok", "triggers-awaited"]
+            | [_, "ok", "triggers-pending"] => {
+                return match field("Version") {
+                    Some(version) => version.parse(),
+                    None => Err(ProbeError::InvalidVersion(format!(
+                        "no version listed for {}",
+                        package
+                    ))),
+                };
+            }
+            // packages which were removed but not purged are still listed, as config-files or not-installed
+            [_, "ok", "config-files"] | [_, "ok", "not-installed"] => {}
+            _ => {
+                return Err(ProbeError::PackageBroken(format!(
+                    "{} has status {}",
+                    package,
+                    status.join(" ")
+                )))
+            }
+        }
+    }
+    Err(ProbeError::PackageNotInstalled(package.to_string()))
+}
+
+/// VersionError is the reason the installed version of a package could not be read
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionError {
+    // dpkg does not list the package as installed
+    NotInstalled,
+    // dpkg lists the package as installed, but its version is invalid or its install did not complete
+    Broken,
+    // the dpkg status file could not be read
+    StatusUnreadable,
+}
+
+impl VersionError {
+    /// get the kind of an error returned by installed_version
+    pub fn of(err: &ProbeError) -> VersionError {
+        match err {
+            ProbeError::PackageNotInstalled(_) => VersionError::NotInstalled,
+            ProbeError::InvalidVersion(_) | ProbeError::PackageBroken(_) => VersionError::Broken,
+            _ => VersionError::StatusUnreadable,
+        }
+    }
+}
+
+/// get the time at which a package was last installed or upgraded, which is when dpkg last wrote
+/// its list of files in the info directory next to the status file
+pub fn install_time(status_path: &Path, package: &str) -> Result<SystemTime, ProbeError> {
+    let info_dir = status_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("info");
+    // the file list of a multi-arch package is named after its architecture too
+    let list = fs::read_dir(&info_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            name == format!("{}.list", package)
+                || (name.starts_with(&format!("{}:", package)) && name.ends_with(".list"))
+        })
+        .ok_or_else(|| ProbeError::PackageNotInstalled(package.to_string()))?;
+    Ok(fs::metadata(list)?.modified()?)
+}
+
+/// helper function which splits a line of a dpkg status paragraph into its field name and value
+/// continuation lines, which start with whitespace, are not fields
+fn split_field(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with(' ') || line.starts_with('\t') {
+        return None;
+    }
+    let (key, value) = line.split_at(line.find(':')?);
+    Some((key.trim(), value[1..].trim()))
+}
+
+/// DebianVersion is a version of a debian package, in the format [epoch:]upstream_version[-debian_revision]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebianVersion {
+    // epoch, which is 0 if not given
+    pub epoch: u32,
+    // upstream version, e.g. 0.2.4 or 1.0~rc1
+    pub upstream: String,
+    // debian revision, which is empty if not given
+    pub revision: String,
+}
+
+impl FromStr for DebianVersion {
+    type Err = ProbeError;
+
+    fn from_str(version: &str) -> Result<DebianVersion, ProbeError> {
+        let invalid = |reason: &str| ProbeError::InvalidVersion(format!("{}: {}", version, reason));
+        let version = version.trim();
+
+        let (epoch, rest) = match version.find(':') {
+            Some(i) => {
+                let epoch = version[..i]
+                    .parse()
+                    .map_err(|_| invalid("epoch is not a number"))?;
+                (epoch, &version[i + 1..])
+            }
+            None => (0, version),
+        };
+        let (upstream, revision) = match rest.rfind('-') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+
+        if !upstream.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(invalid("upstream version must start with a digit"));
+        }
+        let allowed = |c: char| c.is_ascii_alphanumeric() || ".+~-:".contains(c);
+        if !upstream.chars().all(allowed) || !revision.chars().all(allowed) {
+            return Err(invalid("contains characters not allowed in a version"));
+        }
+        if rest.ends_with('-') {
+            return Err(invalid("debian revision is empty"));
+        }
+
+        Ok(DebianVersion {
+            epoch,
+            upstream: upstream.to_string(),
+            revision: revision.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for DebianVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.epoch != 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        write!(f, "{}", self.upstream)?;
+        if !self.revision.is_empty() {
+            write!(f, "-{}", self.revision)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for DebianVersion {
+    /// versions are compared by epoch, then upstream version, then debian revision, following deb-version(7)
+    fn cmp(&self, other: &DebianVersion) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| compare_part(&self.upstream, &other.upstream))
+            .then_with(|| compare_part(&self.revision, &other.revision))
+    }
+}
+
+impl PartialOrd for DebianVersion {
+    fn partial_cmp(&self, other: &DebianVersion) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// helper function which compares the upstream versions or debian revisions of two versions,
+/// alternating between comparing non-digit parts lexically and digit parts numerically
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while !a.is_empty() || !b.is_empty() {
+        // compare the non-digit prefixes, character by character
+        let a_len = a.iter().take_while(|c| !c.is_ascii_digit()).count();
+        let b_len = b.iter().take_while(|c| !c.is_ascii_digit()).count();
+        for i in 0..a_len.max(b_len) {
+            let ordering = char_order(a.get(i).filter(|_| i < a_len))
+                .cmp(&char_order(b.get(i).filter(|_| i < b_len)));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+
+        // compare the digit prefixes as numbers, ignoring leading zeros
+        let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+        let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+        let a_digits = trim_zeros(&a[..a_len]);
+        let b_digits = trim_zeros(&b[..b_len]);
+        let ordering = a_digits
+            .len()
+            .cmp(&b_digits.len())
+            .then_with(|| a_digits.cmp(b_digits));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+    Ordering::Equal
+}
+
+/// helper function which gets the sort order of a character in the non-digit part of a version:
+/// a tilde sorts before anything, even the end of the part, then letters sort before non-letters
+fn char_order(c: Option<&u8>) -> i32 {
+    match c {
+        Some(b'~') => -1,
+        None => 0,
+        Some(c) if c.is_ascii_alphabetic() => i32::from(*c),
+        Some(c) => i32::from(*c) + 256,
+    }
+}
+
+/// helper function which removes leading zeros from a string of digits
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const STATUS: &str = "\
+Package: peach-network
+Status: install ok installed
+Architecture: arm64
+Version: 1:0.2.4-1
+Description: peach-network
+ Version: 9.9.9
+
+Package: peach-oled
+Status: deinstall ok config-files
+Architecture: arm64
+Version: 0.1.3
+
+Package: peach-stats
+Status: install ok installed
+Architecture: arm64
+Multi-Arch: same
+Version: 0.1.2
+
+Package: peach-web
+Status: install ok half-configured
+Architecture: arm64
+Version: 0.3.0
+";
+
+    fn version(version: &str) -> DebianVersion {
+        version.parse().unwrap()
+    }
+
+    /// helper function which writes the fixture status file and the file lists of installed packages
+    /// to a new directory, returning the path of the status file
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("peach-probe-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("info")).unwrap();
+        fs::write(dir.join("info/peach-network.list"), "").unwrap();
+        fs::write(dir.join("info/peach-stats:arm64.list"), "").unwrap();
+        fs::write(dir.join("status"), STATUS).unwrap();
+        dir.join("status")
+    }
+
+    #[test]
+    fn parses_epoch_and_revision() {
+        let parsed = version("1:0.2.4-1ubuntu2");
+        assert_eq!(parsed.epoch, 1);
+        assert_eq!(parsed.upstream, "0.2.4");
+        assert_eq!(parsed.revision, "1ubuntu2");
+        assert_eq!(parsed.to_string(), "1:0.2.4-1ubuntu2");
+        // only the last hyphen separates the revision
+        assert_eq!(version("0.2-rc1-3").upstream, "0.2-rc1");
+        assert!("x1.0".parse::<DebianVersion>().is_err());
+        assert!("1.0-".parse::<DebianVersion>().is_err());
+        assert!("a:1.0".parse::<DebianVersion>().is_err());
+    }
+
+    #[test]
+    fn orders_by_epoch_then_upstream_then_revision() {
+        assert!(version("1:0.1") > version("2.0"));
+        assert!(version("0.2.10") > version("0.2.9"));
+        assert!(version("0.2.4-2") > version("0.2.4-1"));
+        assert!(version("0.2.4-1") > version("0.2.4"));
+    }
+
+    #[test]
+    fn orders_tilde_before_anything() {
+        assert!(version("1.0~rc1") < version("1.0"));
+        assert!(version("1.0~~") < version("1.0~"));
+        assert!(version("1.0~rc1") < version("1.0~rc2"));
+        assert!(version("1.0-1~bpo1") < version("1.0-1"));
+    }
+
+    #[test]
+    fn orders_letters_before_non_letters() {
+        assert!(version("1.0a") < version("1.0+"));
+        assert!(version("1.0a") < version("1.0."));
+        assert!(version("1.0") < version("1.0a"));
+    }
+
+    #[test]
+    fn ignores_leading_zeros() {
+        assert_eq!(version("1.002").cmp(&version("1.2")), Ordering::Equal);
+        assert!(version("1.010") > version("1.9"));
+    }
+
+    #[test]
+    fn reads_installed_version() {
+        let status = fixture("installed-version");
+        assert_eq!(
+            installed_version(&status, "peach-network").unwrap(),
+            version("1:0.2.4-1")
+        );
+        assert_eq!(
+            installed_version(&status, "peach-stats").unwrap(),
+            version("0.1.2")
+        );
+        // removed but not purged
+        assert!(matches!(
+            installed_version(&status, "peach-oled"),
+            Err(ProbeError::PackageNotInstalled(_))
+        ));
+        assert!(matches!(
+            installed_version(&status, "peach-web"),
+            Err(ProbeError::PackageBroken(_))
+        ));
+        assert!(matches!(
+            installed_version(&status, "peach-menu"),
+            Err(ProbeError::PackageNotInstalled(_))
+        ));
+        assert!(matches!(
+            installed_version(&status.with_file_name("missing"), "peach-network"),
+            Err(ProbeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn reads_install_time() {
+        let status = fixture("install-time");
+        assert!(install_time(&status, "peach-network").is_ok());
+        // multi-arch packages have the architecture in the name of their file list
+        assert!(install_time(&status, "peach-stats").is_ok());
+        assert!(matches!(
+            install_time(&status, "peach-oled"),
+            Err(ProbeError::PackageNotInstalled(_))
+        ));
+        // the name of another package must not match as a prefix
+        assert!(install_time(&status, "peach").is_err());
+    }
+}
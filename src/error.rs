@@ -1,32 +1,194 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use peach_lib::error::PeachError;
+use serde_derive::Serialize;
+
 #[derive(Debug)]
 pub enum ProbeError {
-    GetServiceVersionRegexError(regex::Error),
-    GetServiceVersionRegexMatchError,
-    GetServiceVersionParseError(core::str::Utf8Error),
-    GetServiceLogParseError(std::string::FromUtf8Error),
-    GetServiceVersionAptError(std::io::Error),
+    PackageNotInstalled(String),
+    PackageBroken(String),
+    InvalidVersion(String),
+    Io(std::io::Error),
+    Utf8(std::string::FromUtf8Error),
+    Json(serde_json::Error),
+    PeachClientError(PeachError),
+    HttpError(std::io::Error),
+    WebSocketError(tungstenite::Error),
+    WebSocketHandshakeError(String),
+    JsonRpcError { code: i64, message: String },
+    UnexpectedResponse(String),
+    InvalidResponse(String),
+    LatencyExceeded { latency_ms: u64, threshold_ms: u64 },
+    Timeout { timeout_ms: u64 },
+    ProcessError(String),
+    InvalidUrl(String),
+    ConfigError(String),
+}
+
+/// ErrorCategory is the kind of error returned by an endpoint, used to group errors in reports
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    // the request could not be sent or the response could not be received over http
+    HttpTransport,
+    // the request could not be sent or the response could not be received over a websocket
+    WebSocket,
+    // the service returned a JSON-RPC error object
+    JsonRpc,
+    // the response could not be serialized or deserialized
+    Serde,
+    // the service returned a response, but not the one which was expected
+    UnexpectedResponse,
+    // the service returned a response which failed validation
+    InvalidResponse,
+    // the service returned successfully, but exceeded its latency failure threshold
+    LatencyExceeded,
+    // the service did not return before the timeout
+    Timeout,
+    // any other error, such as an io error or an error reading a process
+    Other,
+}
+
+impl ProbeError {
+    /// get the category of this error
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ProbeError::PeachClientError(PeachError::JsonRpcHttp(_)) => {
+                ErrorCategory::HttpTransport
+            }
+            ProbeError::PeachClientError(PeachError::JsonRpcCore(e)) => match e.kind() {
+                jsonrpc_client_core::ErrorKind::JsonRpcError(_) => ErrorCategory::JsonRpc,
+                _ => ErrorCategory::HttpTransport,
+            },
+            ProbeError::HttpError(_) => ErrorCategory::HttpTransport,
+            ProbeError::PeachClientError(PeachError::Serde(_)) | ProbeError::Json(_) => {
+                ErrorCategory::Serde
+            }
+            ProbeError::WebSocketError(_) | ProbeError::WebSocketHandshakeError(_) => {
+                ErrorCategory::WebSocket
+            }
+            ProbeError::JsonRpcError { .. } => ErrorCategory::JsonRpc,
+            ProbeError::UnexpectedResponse(_) => ErrorCategory::UnexpectedResponse,
+            ProbeError::InvalidResponse(_) => ErrorCategory::InvalidResponse,
+            ProbeError::LatencyExceeded { .. } => ErrorCategory::LatencyExceeded,
+            ProbeError::Timeout { .. } => ErrorCategory::Timeout,
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    /// get the kind of the io error which caused this error, if any
+    pub fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            ProbeError::Io(e) | ProbeError::HttpError(e) => Some(e.kind()),
+            ProbeError::WebSocketError(tungstenite::Error::Io(e)) => Some(e.kind()),
+            // the errors of the peach-lib clients wrap the io error of their transport
+            ProbeError::PeachClientError(PeachError::JsonRpcHttp(e)) => io_error_source(e),
+            ProbeError::PeachClientError(PeachError::JsonRpcCore(e)) => io_error_source(e),
+            _ => None,
+        }
+    }
+
+    /// get the code and message of the JSON-RPC error object returned by the service, if any
+    pub fn json_rpc_error(&self) -> Option<(i64, String)> {
+        match self {
+            ProbeError::JsonRpcError { code, message } => Some((*code, message.clone())),
+            ProbeError::PeachClientError(PeachError::JsonRpcCore(e)) => match e.kind() {
+                jsonrpc_client_core::ErrorKind::JsonRpcError(err) => {
+                    Some((err.code.code(), err.message.clone()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
-impl From<regex::Error> for ProbeError {
-    fn from(err: regex::Error) -> ProbeError {
-        ProbeError::GetServiceVersionRegexError(err)
+/// helper function which finds the kind of the first io error in the chain of sources of an error
+fn io_error_source(err: &(dyn Error + 'static)) -> Option<io::ErrorKind> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(e) = err.downcast_ref::<io::Error>() {
+            return Some(e.kind());
+        }
+        source = err.source();
     }
+    None
 }
 
-impl From<core::str::Utf8Error> for ProbeError {
-    fn from(err: core::str::Utf8Error) -> ProbeError {
-        ProbeError::GetServiceVersionParseError(err)
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::PackageNotInstalled(e) => write!(f, "Package not installed: {}", e),
+            ProbeError::PackageBroken(e) => write!(f, "Package install is broken: {}", e),
+            ProbeError::InvalidVersion(e) => write!(f, "Invalid version: {}", e),
+            ProbeError::Io(e) => write!(f, "IO error: {}", e),
+            ProbeError::Utf8(e) => write!(f, "Output is not valid UTF-8: {}", e),
+            ProbeError::Json(e) => write!(f, "JSON error: {}", e),
+            ProbeError::PeachClientError(e) => match e {
+                PeachError::JsonRpcHttp(e) => write!(f, "JsonRpcHTTP error: {}", e),
+                PeachError::JsonRpcCore(e) => write!(f, "JsonRpcCore error: {}", e),
+                PeachError::Serde(e) => write!(f, "Serde Json serialization error: {}", e),
+            },
+            ProbeError::HttpError(e) => write!(f, "HTTP error: {}", e),
+            ProbeError::WebSocketError(e) => write!(f, "WebSocket error: {}", e),
+            ProbeError::WebSocketHandshakeError(e) => write!(f, "WebSocket handshake error: {}", e),
+            ProbeError::JsonRpcError { code, message } => {
+                write!(f, "JsonRpc error with code {}: {}", code, message)
+            }
+            ProbeError::UnexpectedResponse(e) => write!(f, "Unexpected response: {}", e),
+            ProbeError::InvalidResponse(e) => write!(f, "Invalid response: {}", e),
+            ProbeError::LatencyExceeded {
+                latency_ms,
+                threshold_ms,
+            } => write!(
+                f,
+                "Returned successfully after {}ms, exceeding the latency threshold of {}ms",
+                latency_ms, threshold_ms
+            ),
+            ProbeError::Timeout { timeout_ms } => {
+                write!(f, "Timed out after {}ms without returning", timeout_ms)
+            }
+            ProbeError::ProcessError(e) => write!(f, "Process error: {}", e),
+            ProbeError::InvalidUrl(e) => write!(f, "Invalid url: {}", e),
+            ProbeError::ConfigError(e) => write!(f, "Config error: {}", e),
+        }
     }
 }
 
 impl From<std::string::FromUtf8Error> for ProbeError {
     fn from(err: std::string::FromUtf8Error) -> ProbeError {
-        ProbeError::GetServiceLogParseError(err)
+        ProbeError::Utf8(err)
     }
 }
 
 impl From<std::io::Error> for ProbeError {
     fn from(err: std::io::Error) -> ProbeError {
-        ProbeError::GetServiceVersionAptError(err)
+        ProbeError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ProbeError {
+    fn from(err: serde_json::Error) -> ProbeError {
+        ProbeError::Json(err)
+    }
+}
+
+impl From<PeachError> for ProbeError {
+    fn from(err: PeachError) -> ProbeError {
+        ProbeError::PeachClientError(err)
+    }
+}
+
+impl From<tungstenite::Error> for ProbeError {
+    fn from(err: tungstenite::Error) -> ProbeError {
+        ProbeError::WebSocketError(err)
+    }
+}
+
+impl From<toml::de::Error> for ProbeError {
+    fn from(err: toml::de::Error) -> ProbeError {
+        ProbeError::ConfigError(err.to_string())
     }
 }
@@ -0,0 +1,324 @@
+//! Opt-in remediation of detected failures.
+//!
+//! Only a whitelist of safe actions is ever taken, chosen from the suggestions made for each service
+//! (see remediation.rs). After fixing a service it is probed again, to confirm whether the fix worked.
+
+use std::fmt;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::ServiceConfig;
+use crate::dpkg::VersionError;
+use crate::error::ProbeError;
+use crate::probe::{PeachProbe, ProbeOutcome, ProbeResult};
+use crate::remediation::Suggestion;
+use crate::timeout;
+
+/// time to wait after fixing a service before probing it again, so that it has time to start
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// time to wait for systemctl to restart a unit, which is longer than the default stop timeout of systemd
+const RESTART_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// time to wait for apt-get to reinstall a package
+const REINSTALL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// time to wait for wpa_cli to make wpa_supplicant reload its config
+const RECONFIGURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// FixAction is one of the whitelisted actions which --fix can take
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixAction {
+    // restart a systemd unit
+    RestartUnit(String),
+    // reinstall a debian package
+    ReinstallPackage(String),
+    // make wpa_supplicant reload its config
+    ReconfigureWifi,
+}
+
+impl fmt::Display for FixAction {
+    /// the command which is run for this action
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixAction::RestartUnit(unit) => write!(f, "/usr/bin/systemctl restart {}", unit),
+            FixAction::ReinstallPackage(package) => {
+                write!(f, "/usr/bin/apt-get install --reinstall -y {}", package)
+            }
+            FixAction::ReconfigureWifi => write!(f, "/sbin/wpa_cli -i wlan0 reconfigure"),
+        }
+    }
+}
+
+impl FixAction {
+    /// get the actions which would fix the problems suggested for a service, in the order they should be taken
+    /// optional services are only fixed if fix_optional is true, since their problems do not affect the outcome
+    pub fn plan(result: &ProbeResult, fix_optional: bool) -> Vec<FixAction> {
+        let mut actions = Vec::new();
+        if !result.required && !fix_optional {
+            return actions;
+        }
+        for suggestion in &result.suggestions {
+            let action = match suggestion.rule.as_str() {
+                // only a package which dpkg lists as installed, but whose install is broken, is reinstalled,
+                // never one which is not installed or whose unit was not found
+                "unknown_version"
+                    if result.version_error == Some(VersionError::Broken)
+                        && !matches!(&result.unit_status, Some(status) if status.is_not_found()) =>
+                {
+                    FixAction::ReinstallPackage(result.microservice.clone())
+                }
+                // a service which failed with a known log pattern needs its cause fixed, which a restart does not do
+                "offline" | "crash_looping" | "timed_out" | "restart_required" => {
+                    FixAction::RestartUnit(result.unit.clone())
+                }
+                "state_not_restored" => FixAction::ReconfigureWifi,
+                // peach-network returns -32027 when wpa_supplicant has no saved network with the given ssid
+                "json_rpc_error_code" if suggestion_code(result, suggestion) == Some(-32027) => {
+                    FixAction::ReconfigureWifi
+                }
+                // no other problems have a safe fix
+                _ => continue,
+            };
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        // a reinstalled package is only running its new files once its unit is restarted
+        if let Some(position) = actions
+            .iter()
+            .position(|action| matches!(action, FixAction::ReinstallPackage(_)))
+        {
+            let restart = FixAction::RestartUnit(result.unit.clone());
+            actions.retain(|action| action != &restart);
+            actions.insert(position + 1, restart);
+        }
+        actions
+    }
+
+    /// take this action, giving up if its command does not finish within the timeout of the action
+    /// a command which timed out keeps running in the background
+    pub fn run(&self) -> Result<(), ProbeError> {
+        let mut command = match self {
+            FixAction::RestartUnit(unit) => {
+                let mut command = Command::new("/usr/bin/systemctl");
+                command.arg("restart").arg(unit);
+                command
+            }
+            FixAction::ReinstallPackage(package) => {
+                let mut command = Command::new("/usr/bin/apt-get");
+                command
+                    .arg("install")
+                    .arg("--reinstall")
+                    .arg("-y")
+                    .arg(package);
+                command
+            }
+            FixAction::ReconfigureWifi => {
+                let mut command = Command::new("/sbin/wpa_cli");
+                command.arg("-i").arg("wlan0").arg("reconfigure");
+                command
+            }
+        };
+        let output = timeout::run_with_timeout(move || command.output(), Some(self.timeout()))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(ProbeError::ProcessError(format!(
+                "{} failed: {}",
+                self,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+
+    /// get how long to wait for the command of this action to finish
+    fn timeout(&self) -> Duration {
+        match self {
+            FixAction::RestartUnit(_) => RESTART_TIMEOUT,
+            FixAction::ReinstallPackage(_) => REINSTALL_TIMEOUT,
+            FixAction::ReconfigureWifi => RECONFIGURE_TIMEOUT,
+        }
+    }
+}
+
+/// helper function which gets the JSON-RPC error code returned by the endpoint a suggestion was made for
+fn suggestion_code(result: &ProbeResult, suggestion: &Suggestion) -> Option<i64> {
+    let endpoint = result.endpoint(suggestion.endpoint.as_ref()?)?;
+    endpoint.error.as_ref()?.code
+}
+
+/// fix the problems found with each service, then probe the fixed services again to confirm the fixes worked
+/// in dry-run mode, the actions which would be taken are printed without being taken
+/// optional services are only fixed if fix_optional is true
+pub fn fix_services(
+    probe: &mut PeachProbe,
+    services: &[ServiceConfig],
+    dry_run: bool,
+    fix_optional: bool,
+) {
+    let mut fixed = Vec::new();
+    for (index, result) in probe.results.iter().enumerate() {
+        let actions = FixAction::plan(result, fix_optional);
+        if actions.is_empty() {
+            continue;
+        }
+        eprintln!("[ fixing {} ]", result.microservice);
+        let mut applied = Vec::new();
+        for action in actions {
+            if dry_run {
+                eprintln!("++ would run: {}", action);
+                continue;
+            }
+            eprintln!("++ running: {}", action);
+            match action.run() {
+                Ok(()) => applied.push(action.to_string()),
+                Err(e) => eprintln!("++ fix failed: {}", e),
+            }
+        }
+        if !applied.is_empty() {
+            fixed.push((index, ProbeOutcome::of(result), applied));
+        }
+    }
+    if fixed.is_empty() {
+        return;
+    }
+
+    // probe each fixed service again, replacing its result
+    thread::sleep(SETTLE_TIME);
+    for (index, outcome_before, applied) in fixed {
+        let service = &services[index];
+        probe.reprobe_service(index, service);
+        let result = &mut probe.results[index];
+        result.applied_fixes = applied;
+        let outcome = ProbeOutcome::of(result);
+        if outcome == ProbeOutcome::Healthy {
+            eprintln!("++ {} is healthy after fixing", service.name);
+        } else if outcome < outcome_before {
+            eprintln!(
+                "++ {} improved after fixing, but still has problems",
+                service.name
+            );
+        } else {
+            eprintln!("++ fixing {} did not help", service.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{EndpointError, EndpointOutcome, EndpointResult};
+    use crate::remediation;
+    use crate::systemd::UnitStatus;
+
+    /// helper function which builds the result of a service with the given problems, and suggestions for them
+    fn result_with<F>(microservice: &str, problems: F) -> ProbeResult
+    where
+        F: FnOnce(&mut ProbeResult),
+    {
+        let mut result = ProbeResult::new(microservice);
+        result.is_running = true;
+        problems(&mut result);
+        result.suggestions = remediation::suggest(&result);
+        result
+    }
+
+    /// helper function which builds the status of a unit
+    fn unit_status(load_state: &str, active_state: &str) -> UnitStatus {
+        UnitStatus {
+            load_state: load_state.to_string(),
+            active_state: active_state.to_string(),
+            sub_state: "dead".to_string(),
+            main_pid: None,
+            restarts: None,
+            main_start_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn restarts_offline_and_crash_looping_services() {
+        let offline = result_with("peach-web", |result| {
+            result.is_running = false;
+            result.unit_status = Some(unit_status("loaded", "failed"));
+            result.service_log = Some("exited with status 1".to_string());
+        });
+        assert_eq!(
+            FixAction::plan(&offline, false),
+            [FixAction::RestartUnit("peach-web".to_string())]
+        );
+        let crash_looping = result_with("peach-web", |result| result.crash_looping = true);
+        assert_eq!(
+            FixAction::plan(&crash_looping, false),
+            [FixAction::RestartUnit("peach-web".to_string())]
+        );
+    }
+
+    #[test]
+    fn does_not_restart_services_offline_with_a_known_cause() {
+        let result = result_with("peach-web", |result| {
+            result.is_running = false;
+            result.unit_status = Some(unit_status("loaded", "failed"));
+            result.service_log = Some("Error: Address already in use (os error 98)".to_string());
+        });
+        assert!(FixAction::plan(&result, false).is_empty());
+    }
+
+    #[test]
+    fn reinstalls_broken_packages_then_restarts_them() {
+        let result = result_with("peach-stats", |result| {
+            result.version_error = Some(VersionError::Broken);
+            result.version_mismatch = Some("running version differs".to_string());
+        });
+        assert_eq!(
+            FixAction::plan(&result, false),
+            [
+                FixAction::ReinstallPackage("peach-stats".to_string()),
+                FixAction::RestartUnit("peach-stats".to_string())
+            ]
+        );
+
+        // a package whose unit was not found is never reinstalled
+        let result = result_with("peach-stats", |result| {
+            result.version_error = Some(VersionError::Broken);
+            result.unit_status = Some(unit_status("not-found", "inactive"));
+        });
+        assert!(FixAction::plan(&result, false).is_empty());
+    }
+
+    #[test]
+    fn reconfigures_wifi_once() {
+        let result = result_with("peach-network", |result| {
+            result.state_restored = Some(false);
+            result.endpoints.push(EndpointResult {
+                endpoint: "connect".to_string(),
+                outcome: EndpointOutcome::Failure,
+                error: Some(EndpointError::from(&ProbeError::JsonRpcError {
+                    code: -32027,
+                    message: "Failed to find id for given ssid".to_string(),
+                })),
+                latency_ms: Some(1.0),
+                slow: false,
+                skip_reason: None,
+            });
+        });
+        assert_eq!(
+            FixAction::plan(&result, false),
+            [FixAction::ReconfigureWifi]
+        );
+    }
+
+    #[test]
+    fn only_fixes_optional_services_when_asked() {
+        let result = result_with("nginx", |result| {
+            result.required = false;
+            result.crash_looping = true;
+        });
+        assert!(FixAction::plan(&result, false).is_empty());
+        assert_eq!(
+            FixAction::plan(&result, true),
+            [FixAction::RestartUnit("nginx".to_string())]
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Minimal blocking HTTP client, used for probing services which are not covered by peach-lib.
+//!
+//! Requests are made with HTTP/1.0 and `Connection: close`, so that the response body is
+//! simply everything the server sends before closing the connection.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::error::ProbeError;
+
+/// how long to wait for a server to accept or respond to a request before giving up
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HttpResponse stores the parts of an http response which are checked by peach-probe
+pub struct HttpResponse {
+    // numeric status code, e.g. 200
+    pub status: u16,
+    // response headers, with names converted to lowercase
+    pub headers: Vec<(String, String)>,
+    // response body, decoded as utf8 (lossily, so binary assets do not cause errors)
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// get the value of a response header, matching the name case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// make a GET request to the given path on a server at address (host:port)
+pub fn get(address: &str, path: &str) -> Result<HttpResponse, ProbeError> {
+    request(address, "GET", path, None)
+}
+
+/// make a POST request with a json body to the given path on a server at address (host:port)
+pub fn post_json(address: &str, path: &str, body: &str) -> Result<HttpResponse, ProbeError> {
+    request(address, "POST", path, Some(("application/json", body)))
+}
+
+/// split a base url such as http://127.0.0.1:3000/prefix into an address (host:port)
+/// and a path prefix, which is empty if the url has no path
+pub fn split_url(url: &str) -> Result<(String, String), ProbeError> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        ProbeError::InvalidUrl(format!("{} (only http:// urls are supported)", url))
+    })?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    if host.is_empty() {
+        return Err(ProbeError::InvalidUrl(url.to_string()));
+    }
+    // use the default http port if none is given
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((address, path.to_string()))
+}
+
+/// helper function which sends a request and parses the response
+fn request(
+    address: &str,
+    method: &str,
+    path: &str,
+    body: Option<(&str, &str)>,
+) -> Result<HttpResponse, ProbeError> {
+    let raw = send(address, method, path, body).map_err(ProbeError::HttpError)?;
+    parse_response(&raw)
+}
+
+/// helper function which sends a request and reads the raw response
+fn send(
+    address: &str,
+    method: &str,
+    path: &str,
+    body: Option<(&str, &str)>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: peach-probe\r\nConnection: close\r\n",
+        method, path, address
+    );
+    if let Some((content_type, content)) = body {
+        request.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            content.len(),
+            content
+        ));
+    } else {
+        request.push_str("\r\n");
+    }
+    stream.write_all(request.as_bytes())?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    Ok(raw)
+}
+
+/// helper function which parses the status line, headers and body of a raw http response
+fn parse_response(raw: &[u8]) -> Result<HttpResponse, ProbeError> {
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = match text.find("\r\n\r\n") {
+        Some(index) => (&text[..index], &text[index + 4..]),
+        None => (&text[..], ""),
+    };
+    let mut lines = head.split("\r\n");
+
+    // status line has the form: HTTP/1.1 200 OK
+    let status_line = lines.next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| ProbeError::UnexpectedResponse(status_line.to_string()))?;
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?.trim().to_lowercase();
+            let value = parts.next()?.trim().to_string();
+            Some((name, value))
+        })
+        .collect();
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: body.to_string(),
+    };
+    // some servers still use chunked encoding for HTTP/1.0 requests
+    if response.header("transfer-encoding") == Some("chunked") {
+        response.body = decode_chunked(&response.body);
+    }
+    Ok(response)
+}
+
+/// helper function which joins the chunks of a body sent with chunked transfer encoding
+fn decode_chunked(body: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = body;
+    while let Some(index) = rest.find("\r\n") {
+        let size = usize::from_str_radix(rest[..index].trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        let start = index + 2;
+        let end = (start + size).min(rest.len());
+        decoded.push_str(rest.get(start..end).unwrap_or_default());
+        rest = rest.get(end + 2..).unwrap_or_default();
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_urls() {
+        assert_eq!(
+            split_url("http://127.0.0.1:3000").unwrap(),
+            ("127.0.0.1:3000".to_string(), "".to_string())
+        );
+        assert_eq!(
+            split_url("http://peach.local/prefix/").unwrap(),
+            ("peach.local:80".to_string(), "/prefix".to_string())
+        );
+        assert!(matches!(
+            split_url("https://peach.local"),
+            Err(ProbeError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            split_url("http:///path"),
+            Err(ProbeError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn parses_responses() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nX-Empty:\r\n\r\n<html></html>";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.header("x-empty"), Some(""));
+        assert_eq!(response.header("location"), None);
+        assert_eq!(response.body, "<html></html>");
+
+        let response = parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, "");
+    }
+
+    #[test]
+    fn rejects_invalid_status_lines() {
+        assert!(matches!(
+            parse_response(b"SSH-2.0-OpenSSH_7.9\r\n"),
+            Err(ProbeError::UnexpectedResponse(_))
+        ));
+        assert!(matches!(
+            parse_response(b""),
+            Err(ProbeError::UnexpectedResponse(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7\r\n, peach\r\n0\r\n\r\n";
+        assert_eq!(parse_response(raw).unwrap().body, "hello, peach");
+    }
+}
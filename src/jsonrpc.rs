@@ -0,0 +1,45 @@
+//! Helpers for making raw JSON-RPC 2.0 calls, for services and methods not covered by peach-lib.
+
+use serde_json::{json, Value};
+
+use crate::error::ProbeError;
+use crate::http_client;
+
+/// build a JSON-RPC 2.0 request object
+pub fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": id,
+    })
+}
+
+/// extract the result from a JSON-RPC 2.0 response object,
+/// converting an error object into ProbeError::JsonRpcError
+pub fn response_result(response: &Value) -> Result<Value, ProbeError> {
+    if let Some(err) = response.get("error") {
+        return Err(ProbeError::JsonRpcError {
+            code: err["code"].as_i64().unwrap_or_default(),
+            message: err["message"].as_str().unwrap_or_default().to_string(),
+        });
+    }
+    match response.get("result") {
+        Some(result) => Ok(result.clone()),
+        None => Err(ProbeError::UnexpectedResponse(response.to_string())),
+    }
+}
+
+/// call a method on a JSON-RPC server listening for http requests at address (host:port)
+pub fn call(address: &str, method: &str, params: Value) -> Result<Value, ProbeError> {
+    let body = request(1, method, params).to_string();
+    let response = http_client::post_json(address, "/", &body)?;
+    if response.status != 200 {
+        return Err(ProbeError::UnexpectedResponse(format!(
+            "http status {}",
+            response.status
+        )));
+    }
+    let response: Value = serde_json::from_str(&response.body)?;
+    response_result(&response)
+}
@@ -3,11 +3,36 @@ use log::info;
 use clap::arg_enum;
 use structopt::StructOpt;
 
+mod buttons_client;
+mod compat;
+mod config;
+mod contract;
+mod crash_loop;
+mod dpkg;
 mod error;
+mod fix;
+mod http_client;
+mod jsonrpc;
+mod metrics;
+mod network_state;
 mod probe;
+mod process;
+mod remediation;
+mod report;
+mod shape;
+mod systemd;
+mod timeout;
+mod validate;
 mod vars;
+mod watch;
 
-use crate::probe::PeachProbe;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::{LatencyThreshold, ProbeConfig, ServiceConfig, DEFAULT_CONFIG_PATH};
+use crate::crash_loop::CrashLoopDetector;
+use crate::probe::{PeachProbe, ProbeOutcome};
+use crate::report::ReportFormat;
 
 #[derive(StructOpt, Debug)]
 #[structopt(
@@ -17,12 +42,78 @@ use crate::probe::PeachProbe;
 struct Opt {
     #[structopt(short, long)]
     verbose: bool,
-    #[structopt(possible_values = &Microservice::variants(), case_insensitive = true)]
-    services: Vec<Microservice>,
+    /// skip endpoints which change the state of a microservice, such as switching network mode or powering off the display
+    #[structopt(long, visible_alias = "read-only")]
+    safe: bool,
+    /// format of the final report printed to stdout
+    #[structopt(
+        short,
+        long,
+        default_value = "text",
+        possible_values = &ReportFormat::variants(),
+        case_insensitive = true
+    )]
+    format: ReportFormat,
+    /// base url at which the routes of peach-web are probed [default: http://127.0.0.1:3000]
+    #[structopt(long)]
+    web_url: Option<String>,
+    /// latency in milliseconds above which an endpoint is reported as slow, unless set for the endpoint in the config
+    #[structopt(long)]
+    latency_warn_ms: Option<u64>,
+    /// latency in milliseconds above which an endpoint is reported as failing, unless set for the endpoint in the config
+    #[structopt(long)]
+    latency_fail_ms: Option<u64>,
+    /// time in milliseconds to wait for an endpoint before reporting it as timed out, unless set for the endpoint in the config
+    #[structopt(long, default_value = "30000")]
+    timeout_ms: u64,
+    /// total time in milliseconds allowed for probing the endpoints of each service, unless set in the config
+    #[structopt(long)]
+    service_timeout_ms: Option<u64>,
+    /// number of services to probe at the same time (services with side effects are always probed alone)
+    #[structopt(short, long, default_value = "1")]
+    jobs: usize,
+    /// try to fix detected failures with safe actions, such as restarting an offline service, then probe again
+    #[structopt(long, conflicts_with_all = &["safe", "watch", "export"])]
+    fix: bool,
+    /// with --fix, print the actions which would be taken without taking them
+    #[structopt(long, requires = "fix")]
+    dry_run: bool,
+    /// probe the services repeatedly, printing only changes in their state, instead of printing a report
+    /// (implies --safe, unless --mutating is given)
+    #[structopt(short, long)]
+    watch: bool,
+    /// with --watch, also call endpoints which change the state of a microservice on every run
+    #[structopt(long, requires = "watch", conflicts_with = "safe")]
+    mutating: bool,
+    /// probe the services repeatedly, serving their results as prometheus metrics at http://<export>/metrics
+    /// (requires --safe, so that mutating endpoints are not called on every run)
+    #[structopt(long, requires = "safe")]
+    export: Option<String>,
+    /// number of restarts within --crash-loop-window above which a running service is reported as crash-looping
+    #[structopt(long, default_value = "3")]
+    crash_loop_restarts: u32,
+    /// time in seconds within which restarts are counted to detect crash-looping services
+    #[structopt(long, default_value = "600")]
+    crash_loop_window: u64,
+    /// time in seconds between runs in watch and export mode
+    #[structopt(long, default_value = "60")]
+    interval: u64,
+    /// number of runs kept in the history in watch mode
+    #[structopt(long, default_value = "10")]
+    history: usize,
+    /// path of the dpkg status file, from which installed versions are read
+    #[structopt(long, parse(from_os_str), default_value = "/var/lib/dpkg/status")]
+    dpkg_status: PathBuf,
+    /// path of a TOML file describing the services to probe [default: /etc/peach-probe/services.toml]
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// names of services to probe, as listed in the config (built-in services are peach_oled,
+    /// peach_network, peach_stats, peach_menu, peach_web, peach_buttons and peach_monitor)
+    services: Vec<String>,
 }
 
 arg_enum! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     #[allow(non_camel_case_types)]
     #[allow(clippy::enum_variant_names)]
     pub enum Microservice {
@@ -31,7 +122,8 @@ arg_enum! {
         Peach_Stats,
         Peach_Menu,
         Peach_Web,
-        Peach_Buttons
+        Peach_Buttons,
+        Peach_Monitor
     }
 }
 
@@ -47,9 +139,34 @@ impl Microservice {
             Microservice::Peach_Menu => "peach-menu",
             Microservice::Peach_Web => "peach-web",
             Microservice::Peach_Buttons => "peach-buttons",
+            Microservice::Peach_Monitor => "peach-monitor",
         };
         s.to_string()
     }
+
+    /// get the name of the environment variable which peach-lib uses for the address of this microservice,
+    /// for microservices whose address can be configured
+    pub fn get_address_var(service: &Microservice) -> Option<&'static str> {
+        match service {
+            Microservice::Peach_Oled => Some("PEACH_OLED_SERVER"),
+            Microservice::Peach_Network => Some("PEACH_NETWORK_SERVER"),
+            Microservice::Peach_Stats => Some("PEACH_STATS_SERVER"),
+            Microservice::Peach_Buttons => Some("PEACH_BUTTONS_SERVER"),
+            _ => None,
+        }
+    }
+
+    /// get the address which peach-lib uses for this microservice if its environment variable is not set
+    pub fn get_default_address(service: &Microservice) -> &'static str {
+        match service {
+            Microservice::Peach_Oled => vars::DEFAULT_OLED_SERVER,
+            Microservice::Peach_Network => vars::DEFAULT_NETWORK_SERVER,
+            Microservice::Peach_Stats => vars::DEFAULT_STATS_SERVER,
+            Microservice::Peach_Buttons => buttons_client::DEFAULT_BUTTONS_SERVER,
+            Microservice::Peach_Web => "127.0.0.1:3000",
+            Microservice::Peach_Menu | Microservice::Peach_Monitor => "",
+        }
+    }
 }
 
 fn main() {
@@ -65,69 +182,124 @@ fn main() {
         info!("using verbose mode")
     }
 
-    let services;
-    // if not arguments were provided, then we probe all services
-    if opt.services.is_empty() {
-        services = vec![
-            Microservice::Peach_Network,
-            Microservice::Peach_Oled,
-            Microservice::Peach_Stats,
-            Microservice::Peach_Web,
-            Microservice::Peach_Buttons,
-            Microservice::Peach_Menu,
-        ]
+    // load the registry of services, falling back to the built-in microservices
+    // if no config path was given and the default config file does not exist
+    let config = match &opt.config {
+        Some(path) => ProbeConfig::load(path),
+        None if PathBuf::from(DEFAULT_CONFIG_PATH).exists() => {
+            ProbeConfig::load(&PathBuf::from(DEFAULT_CONFIG_PATH))
+        }
+        None => Ok(ProbeConfig::default()),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error loading config: {}", err);
+            std::process::exit(ProbeOutcome::ProbeError as i32);
+        }
+    };
+
+    // if no arguments were provided, then we probe all services
+    let services: Vec<ServiceConfig> = if opt.services.is_empty() {
+        config.services.clone()
     } else {
-        services = opt.services;
+        opt.services
+            .iter()
+            .map(|name| match config.find(name) {
+                Some(service) => service.clone(),
+                None => {
+                    eprintln!("error: unknown service {}", name);
+                    std::process::exit(1);
+                }
+            })
+            .collect()
+    };
+
+    // peach-lib reads the addresses of microservices from environment variables,
+    // so set these for any services with a configured address
+    for service in &services {
+        if let (Some(address), Some(var)) = (
+            &service.address,
+            service
+                .builtin_probe()
+                .as_ref()
+                .and_then(Microservice::get_address_var),
+        ) {
+            std::env::set_var(var, address);
+        }
     }
 
+    // the base url of peach-web can be set on the command line or in the config
+    let web_url = opt.web_url.clone().or_else(|| {
+        services
+            .iter()
+            .find(|service| service.builtin_probe() == Some(Microservice::Peach_Web))
+            .and_then(|service| service.address.as_ref())
+            .map(|address| format!("http://{}", address))
+    });
+
     // instantiate the probe
-    let mut probe: PeachProbe = PeachProbe::new(opt.verbose);
+    // when printing a json or junit report or watching, progress messages are sent to stderr to keep stdout parseable
+    let progress_on_stderr =
+        opt.watch || opt.export.is_some() || !matches!(opt.format, ReportFormat::Text);
+    let mut probe: PeachProbe = PeachProbe::new(opt.verbose, progress_on_stderr);
+    // watch and export mode report changes and metrics instead, so nothing is printed while probing
+    probe.quiet = opt.watch || opt.export.is_some();
+    if let Some(web_url) = web_url {
+        probe.web_url = web_url;
+    }
+    // watch mode would otherwise switch network mode and power off the display on every run,
+    // so it only calls mutating endpoints when asked to explicitly
+    probe.read_only = opt.safe || (opt.watch && !opt.mutating);
+    probe.dpkg_status_path = opt.dpkg_status.clone();
+    probe.default_latency = LatencyThreshold {
+        warn_ms: opt.latency_warn_ms,
+        fail_ms: opt.latency_fail_ms,
+        timeout_ms: Some(opt.timeout_ms),
+    };
+    probe.default_service_timeout_ms = opt.service_timeout_ms;
+    probe.compatibility = config.compatibility;
+    probe.crash_loop = CrashLoopDetector::new(
+        opt.crash_loop_restarts,
+        Duration::from_secs(opt.crash_loop_window),
+    );
+
+    // in export mode, probe the services on an interval and serve metrics until peach-probe is killed
+    if let Some(address) = &opt.export {
+        let interval = Duration::from_secs(opt.interval);
+        if let Err(err) = metrics::serve(address, probe, services, opt.jobs, interval) {
+            eprintln!("error serving metrics: {}", err);
+        }
+        std::process::exit(ProbeOutcome::ProbeError as i32);
+    }
+
+    // in watch mode, probe the services on an interval until peach-probe is killed
+    if opt.watch {
+        watch::Watcher::new(opt.history).run(
+            &mut probe,
+            &services,
+            opt.jobs,
+            Duration::from_secs(opt.interval),
+        );
+    }
 
-    // iterate through services and run probe tests on them
-    for service in services {
-        probe.probe_service(service);
+    // run probe tests on all services, with up to opt.jobs services probed at the same time
+    probe.probe_services(&services, opt.jobs);
+
+    // try to fix any failures, probing the fixed services again
+    if opt.fix {
+        // optional services are only fixed if they were named on the command line
+        let fix_optional = !opt.services.is_empty();
+        fix::fix_services(&mut probe, &services, opt.dry_run, fix_optional);
     }
 
     // final report of how many microservices returned successes and failures
-    println!("[ generating report ]");
-    for result in probe.results {
-        let num_failures = result.failures.len();
-        let report;
-        // if service is running according to systemctl status
-        if result.is_running {
-            if num_failures == 0 {
-                report = format!(
-                    "- {} [version: {}] is online.",
-                    result.microservice, result.version
-                );
-                println!("{}", report);
-            }
-            // even if its running, some endpoints could still return errors
-            else {
-                report = format!(
-                    "- {} [version: {}] is online but {} endpoints returned errors: {:?}",
-                    result.microservice, result.version, num_failures, result.failures
-                );
-                eprintln!("{}", report);
-            }
-        }
-        // if service is not running according to systemctl status, print the service log
-        else {
-            match result.service_log {
-                Some(service_log) => {
-                    report = format!(
-                        "- {} [version: {}] is offline, with log:\n {}",
-                        result.microservice, result.version, service_log
-                    );
-                }
-                None => {
-                    report = format!(
-                        "- {} [version: {}] is offline, log not found",
-                        result.microservice, result.version
-                    );
-                }
-            };
-            eprintln!("{}", report);
-        }
+    let mut outcome = probe.outcome();
+    if let Err(err) = report::print_report(&probe.results, &opt.format, opt.verbose) {
+        eprintln!("error generating report: {:#?}", err);
+        outcome = ProbeOutcome::ProbeError;
     }
+
+    // exit with a code which reflects the most severe problem found
+    std::process::exit(outcome as i32);
 }
@@ -0,0 +1,356 @@
+//! Export mode, in which probe results are served as Prometheus metrics.
+//!
+//! Services are probed on an interval by a background thread, and the metrics derived from the most
+//! recent results are served at `/metrics` in the Prometheus text exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{BufRead, BufReader, Write as IoWrite};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::ServiceConfig;
+use crate::error::ProbeError;
+use crate::probe::{EndpointOutcome, PeachProbe, ProbeResult};
+
+/// how long to wait for a client to send its request or receive the response
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// upper bounds in seconds of the buckets of the endpoint latency histograms
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+
+/// Histogram stores the distribution of the latencies of an endpoint over all runs
+#[derive(Default)]
+struct Histogram {
+    // number of observations less than or equal to each bound in LATENCY_BUCKETS
+    buckets: [u64; 10],
+    // sum of all observations, in seconds
+    sum: f64,
+    // number of observations
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Metrics stores everything which is exported, derived from the results of every run
+#[derive(Default)]
+pub struct Metrics {
+    // results of the most recent run
+    results: Vec<ProbeResult>,
+    // latency histograms, keyed by service and endpoint
+    latencies: BTreeMap<(String, String), Histogram>,
+    // number of runs which have been completed
+    runs: u64,
+    // unix time at which the most recent run finished
+    last_run_timestamp: u64,
+    // wall-clock time taken by the most recent run, in seconds
+    last_run_duration: f64,
+    // true if the thread which probes the services has stopped, so the metrics are no longer updated
+    probe_stopped: bool,
+}
+
+/// ProbeThreadGuard marks the metrics as stale when the probe thread stops, including when it panics
+struct ProbeThreadGuard {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl Drop for ProbeThreadGuard {
+    fn drop(&mut self) {
+        // the lock is poisoned if the thread panicked while recording, which does not matter here
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        metrics.probe_stopped = true;
+    }
+}
+
+impl Metrics {
+    /// update the metrics with the results of a run
+    pub fn record(&mut self, results: Vec<ProbeResult>, duration: Duration) {
+        for result in &results {
+            for endpoint in &result.endpoints {
+                if let Some(latency_ms) = endpoint.latency_ms {
+                    self.latencies
+                        .entry((result.microservice.clone(), endpoint.endpoint.clone()))
+                        .or_default()
+                        .observe(latency_ms / 1000.0);
+                }
+            }
+        }
+        self.results = results;
+        self.runs += 1;
+        self.last_run_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        self.last_run_duration = duration.as_secs_f64();
+    }
+
+    /// render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "peach_probe_runs_total",
+            "counter",
+            "number of probe runs completed",
+        );
+        let _ = writeln!(out, "peach_probe_runs_total {}", self.runs);
+        header(
+            &mut out,
+            "peach_probe_last_run_timestamp_seconds",
+            "gauge",
+            "unix time at which the most recent probe run finished",
+        );
+        let _ = writeln!(
+            out,
+            "peach_probe_last_run_timestamp_seconds {}",
+            self.last_run_timestamp
+        );
+        header(
+            &mut out,
+            "peach_probe_last_run_duration_seconds",
+            "gauge",
+            "time taken by the most recent probe run",
+        );
+        let _ = writeln!(
+            out,
+            "peach_probe_last_run_duration_seconds {}",
+            self.last_run_duration
+        );
+
+        header(
+            &mut out,
+            "peach_probe_service_running",
+            "gauge",
+            "1 if the systemd unit of the service is running",
+        );
+        for result in &self.results {
+            let _ = writeln!(
+                out,
+                "peach_probe_service_running{{service=\"{}\"}} {}",
+                escape(&result.microservice),
+                result.is_running as u8
+            );
+        }
+
+        header(
+            &mut out,
+            "peach_probe_service_crash_looping",
+            "gauge",
+            "1 if systemd restarted the service too many times within the crash-loop window",
+        );
+        for result in &self.results {
+            let _ = writeln!(
+                out,
+                "peach_probe_service_crash_looping{{service=\"{}\"}} {}",
+                escape(&result.microservice),
+                result.crash_looping as u8
+            );
+        }
+
+        header(
+            &mut out,
+            "peach_probe_error",
+            "gauge",
+            "1 if peach-probe itself hit errors while probing the service in the most recent run, such as calls from a previous run which have still not returned",
+        );
+        for result in &self.results {
+            let _ = writeln!(
+                out,
+                "peach_probe_error{{service=\"{}\"}} {}",
+                escape(&result.microservice),
+                !result.probe_errors.is_empty() as u8
+            );
+        }
+
+        header(
+            &mut out,
+            "peach_probe_service_info",
+            "gauge",
+            "installed version of the service",
+        );
+        for result in &self.results {
+            let _ = writeln!(
+                out,
+                "peach_probe_service_info{{service=\"{}\",version=\"{}\"}} 1",
+                escape(&result.microservice),
+                escape(&result.version)
+            );
+        }
+
+        header(&mut out, "peach_probe_endpoint_success", "gauge", "1 if the endpoint returned successfully in the most recent run, 0 if it failed, returned an invalid response or timed out");
+        for result in &self.results {
+            // skipped endpoints were not called, so have no success or failure
+            let outcomes = result
+                .endpoints
+                .iter()
+                .filter(|endpoint| endpoint.outcome != EndpointOutcome::Skipped)
+                .map(|endpoint| {
+                    let success = endpoint.outcome == EndpointOutcome::Success;
+                    (&endpoint.endpoint, success as u8)
+                });
+            for (endpoint, success) in outcomes {
+                let _ = writeln!(
+                    out,
+                    "peach_probe_endpoint_success{{service=\"{}\",endpoint=\"{}\"}} {}",
+                    escape(&result.microservice),
+                    escape(endpoint),
+                    success
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "peach_probe_endpoint_latency_seconds",
+            "histogram",
+            "wall-clock latency of endpoint calls",
+        );
+        for ((service, endpoint), histogram) in &self.latencies {
+            let labels = format!(
+                "service=\"{}\",endpoint=\"{}\"",
+                escape(service),
+                escape(endpoint)
+            );
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+                let _ = writeln!(
+                    out,
+                    "peach_probe_endpoint_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "peach_probe_endpoint_latency_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "peach_probe_endpoint_latency_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "peach_probe_endpoint_latency_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        out
+    }
+}
+
+/// probe the given services every interval in the background, and serve the metrics derived from
+/// their results at http://address/metrics, forever
+pub fn serve(
+    address: &str,
+    mut probe: PeachProbe,
+    services: Vec<ServiceConfig>,
+    jobs: usize,
+    interval: Duration,
+) -> Result<(), ProbeError> {
+    let listener = TcpListener::bind(address)?;
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+
+    let probe_metrics = metrics.clone();
+    thread::spawn(move || {
+        let guard = ProbeThreadGuard {
+            metrics: probe_metrics,
+        };
+        loop {
+            let start = Instant::now();
+            probe.results.clear();
+            probe.probe_services(&services, jobs);
+            let results = std::mem::take(&mut probe.results);
+            if let Ok(mut metrics) = guard.metrics.lock() {
+                metrics.record(results, start.elapsed());
+            }
+            if let Some(remaining) = interval.checked_sub(start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    });
+
+    eprintln!("serving metrics at http://{}/metrics", address);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = handle_request(stream, &metrics) {
+                    eprintln!("error serving metrics: {}", e);
+                }
+            }
+            Err(e) => eprintln!("error accepting connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// helper function which responds to a single http request, serving the metrics at /metrics
+fn handle_request(stream: TcpStream, metrics: &Mutex<Metrics>) -> Result<(), ProbeError> {
+    // connections are handled one at a time, so a slow client must not block other scrapes for long
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // read the rest of the request headers, which are not needed
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line.trim_end() != "" {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let metrics = metrics.lock().unwrap_or_else(|e| e.into_inner());
+            // serving the last metrics forever would hide that the probe has stopped, so the scrape fails instead
+            if metrics.probe_stopped {
+                (
+                    "503 Service Unavailable",
+                    "text/plain",
+                    "the probe thread has stopped, so the metrics are stale\n".to_string(),
+                )
+            } else {
+                ("200 OK", "text/plain; version=0.0.4", metrics.render())
+            }
+        }
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// helper function which writes the HELP and TYPE lines of a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// helper function which escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,251 @@
+//! Snapshot and restore of the network state changed by the peach-network probe.
+//!
+//! The peach-network probe adds and forgets a test network, reconfigures wpa_supplicant and switches
+//! between AP and client mode. Before any of this, the current mode, ssid, saved networks and
+//! wpa_supplicant config are captured, and afterwards they are restored. Restoring is done by a guard,
+//! so the state is also restored if the code of the probe itself panics while probing. A panic within an
+//! endpoint call happens on the thread of the call, and is reported as an error of that endpoint instead.
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use peach_lib::network_client;
+
+use crate::error::ProbeError;
+use crate::timeout::{self, InFlight};
+use crate::vars::WPA_SUPPLICANT_CONFIG_PATH;
+
+/// how long to wait for the device to reconnect to its network after the state is restored
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// NetworkSnapshot stores the network state of the device before it was changed by peach-probe
+pub struct NetworkSnapshot {
+    // true if the device was in access point mode, rather than client mode
+    ap_mode: bool,
+    // ssid of the network the device was connected to in client mode
+    ssid: Option<String>,
+    // saved networks as returned by peach-network
+    saved_networks: Option<String>,
+    // contents of the wpa_supplicant config, if it could be read
+    wpa_supplicant_config: Option<String>,
+    // how long to wait for each call to peach-network
+    timeout: Option<Duration>,
+    // calls to peach-network which have not finished, including calls which timed out
+    calls: InFlight,
+}
+
+impl NetworkSnapshot {
+    /// capture the current network state, giving up on each call to peach-network after timeout
+    /// calls are counted in calls until they finish, both while capturing and restoring
+    pub fn capture(
+        timeout: Option<Duration>,
+        calls: &InFlight,
+    ) -> Result<NetworkSnapshot, ProbeError> {
+        let ap_mode = is_ap_mode(timeout, calls)?;
+        let ssid = if ap_mode {
+            None
+        } else {
+            timeout::run_with_timeout(calls.track(|| network_client::ssid("wlan0")), timeout).ok()
+        };
+        let saved_networks =
+            timeout::run_with_timeout(calls.track(network_client::saved_networks), timeout)?;
+        // the config is only readable as root, in which case restoring falls back to forgetting the test network
+        let wpa_supplicant_config = fs::read_to_string(WPA_SUPPLICANT_CONFIG_PATH).ok();
+        Ok(NetworkSnapshot {
+            ap_mode,
+            ssid,
+            saved_networks,
+            wpa_supplicant_config,
+            timeout,
+            calls: calls.clone(),
+        })
+    }
+
+    /// get a guard which restores this snapshot when it is dropped
+    pub fn guard(self) -> RestoreGuard {
+        RestoreGuard {
+            snapshot: Some(self),
+        }
+    }
+
+    /// restore the network state of the device to this snapshot
+    /// returns a description of each part of the state which could not be restored
+    pub fn restore(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let timeout = self.timeout;
+        let calls = &self.calls;
+
+        // restore the saved networks, by rewriting the wpa_supplicant config if it was read,
+        // or otherwise by forgetting the test network added by peach-probe
+        let config_restored = match &self.wpa_supplicant_config {
+            Some(config) => {
+                let current = fs::read_to_string(WPA_SUPPLICANT_CONFIG_PATH).ok();
+                if current.as_ref() == Some(config) {
+                    Ok(())
+                } else {
+                    write_atomically(Path::new(WPA_SUPPLICANT_CONFIG_PATH), config)
+                        .map_err(ProbeError::from)
+                        .and_then(|_| {
+                            timeout::run_with_timeout(
+                                calls.track(network_client::reconfigure),
+                                timeout,
+                            )
+                        })
+                        .map(|_| ())
+                }
+            }
+            None => timeout::run_with_timeout(
+                calls.track(|| network_client::forget("wlan0", "peach-probe-test-ssid")),
+                timeout,
+            )
+            .map(|_| ()),
+        };
+        if let Err(e) = config_restored {
+            problems.push(format!("error restoring saved networks: {}", e));
+        }
+        match timeout::run_with_timeout(calls.track(network_client::saved_networks), timeout) {
+            Ok(saved_networks) if saved_networks == self.saved_networks => (),
+            Ok(_) => problems.push("saved networks differ from before probing".to_string()),
+            Err(e) => problems.push(format!("error checking saved networks: {}", e)),
+        }
+
+        // switch back to the mode the device was in
+        let mode_restored = is_ap_mode(timeout, calls).and_then(|ap_mode| {
+            if ap_mode == self.ap_mode {
+                Ok(())
+            } else if self.ap_mode {
+                timeout::run_with_timeout(calls.track(network_client::activate_ap), timeout)
+                    .map(|_| ())
+            } else {
+                timeout::run_with_timeout(calls.track(network_client::activate_client), timeout)
+                    .map(|_| ())
+            }
+        });
+        if let Err(e) = mode_restored {
+            problems.push(format!("error restoring network mode: {}", e));
+        }
+
+        // in client mode, wait for the device to reconnect to the network it was connected to
+        if let Some(ssid) = &self.ssid {
+            let start = Instant::now();
+            loop {
+                let current = timeout::run_with_timeout(
+                    calls.track(|| network_client::ssid("wlan0")),
+                    timeout,
+                );
+                match current {
+                    Ok(current) if &current == ssid => break,
+                    _ if start.elapsed() > RECONNECT_TIMEOUT => {
+                        problems.push(format!("device did not reconnect to {}", ssid));
+                        break;
+                    }
+                    _ => thread::sleep(Duration::from_secs(1)),
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// RestoreGuard restores a network snapshot when it is dropped, unless it has already been restored
+pub struct RestoreGuard {
+    snapshot: Option<NetworkSnapshot>,
+}
+
+impl RestoreGuard {
+    /// restore the snapshot now, returning a description of each part of the state which could not be restored
+    pub fn restore(mut self) -> Vec<String> {
+        match self.snapshot.take() {
+            Some(snapshot) => snapshot.restore(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        // only reached without restoring if the code of the probe panicked, and not an endpoint call
+        if let Some(snapshot) = self.snapshot.take() {
+            eprintln!("restoring network state after peach-probe panicked");
+            for problem in snapshot.restore() {
+                eprintln!("error restoring network state: {}", problem);
+            }
+        }
+    }
+}
+
+/// helper function which replaces the contents of a file by writing them to a temporary file in the same directory
+/// and renaming it over the original, so that the original is never left truncated if writing fails
+/// the new file keeps the permissions of the original, or is only readable by its owner if there was no original
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".peach-probe.tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let permissions = fs::metadata(path).map(|metadata| metadata.permissions());
+
+    let written = (|| {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        if let Ok(permissions) = permissions {
+            fs::set_permissions(&temp_path, permissions)?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// helper function which returns true if the device is in access point mode
+fn is_ap_mode(timeout: Option<Duration>, calls: &InFlight) -> Result<bool, ProbeError> {
+    timeout::run_with_timeout(calls.track(|| network_client::state("ap0")), timeout)
+        .map(|state| state == "up")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn writes_atomically_keeping_permissions() {
+        let dir = std::env::temp_dir().join(format!("peach-probe-write-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wpa_supplicant-wlan0.conf");
+        fs::write(&path, "network={\n}\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomically(&path, "ctrl_interface=/run/wpa_supplicant\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "ctrl_interface=/run/wpa_supplicant\n"
+        );
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        // the temporary file is renamed over the original, so nothing else is left in the directory
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // a file which did not exist is created readable only by its owner
+        let new_path = dir.join("new.conf");
+        write_atomically(&new_path, "").unwrap();
+        assert_eq!(
+            fs::metadata(&new_path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+}
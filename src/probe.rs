@@ -4,38 +4,281 @@ use peach_lib::oled_client;
 use peach_lib::stats_client;
 
 use log::info;
-use regex::Regex;
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use crate::error::ProbeError;
-use crate::vars::PEACH_LOGO;
+use crate::buttons_client::{ButtonsClient, DEFAULT_BUTTONS_SERVER};
+use crate::compat::{self, CompatibilityMatrix};
+use crate::config::{Check, LatencyThreshold, ServiceConfig};
+use crate::contract::Contract;
+use crate::crash_loop::CrashLoopDetector;
+use crate::dpkg::{self, DebianVersion, VersionError};
+use crate::error::{ErrorCategory, ProbeError};
+use crate::http_client;
+use crate::jsonrpc;
+use crate::network_state::NetworkSnapshot;
+use crate::process;
+use crate::remediation::{self, Suggestion};
+use crate::systemd::{self, UnitStatus};
+use crate::timeout::{self, InFlight};
+use crate::validate::{self, Validator};
+use crate::vars::{
+    DEFAULT_DPKG_STATUS_PATH, DEFAULT_OLED_SERVER, DEFAULT_WEB_URL, MONITOR_DATA_PATH,
+    MONITOR_THRESHOLD_PATH, PEACH_LOGO, WEB_ROUTES,
+};
 use crate::Microservice;
 
+/// how long to wait for mutating calls which timed out to finish, before restoring the state they changed
+const MUTATING_CALL_WAIT: Duration = Duration::from_secs(30);
+
+/// EndpointOutcome is the outcome of probing a particular endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointOutcome {
+    // the endpoint returned successfully
+    Success,
+    // the endpoint returned an error, or exceeded its latency failure threshold
+    Failure,
+    // the endpoint returned successfully, but with a response which failed validation
+    InvalidResponse,
+    // the endpoint did not return before its timeout
+    Timeout,
+    // the endpoint was not called, because it is mutating and the probe was read-only
+    Skipped,
+}
+
+impl EndpointOutcome {
+    /// returns true if this outcome means the endpoint is not working
+    pub fn is_failing(self) -> bool {
+        matches!(
+            self,
+            EndpointOutcome::Failure | EndpointOutcome::InvalidResponse | EndpointOutcome::Timeout
+        )
+    }
+}
+
+/// EndpointError stores the details of an error returned by a particular endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointError {
+    // kind of error which was returned
+    pub category: ErrorCategory,
+    // code of the JSON-RPC error object returned by the service, if any
+    pub code: Option<i64>,
+    // description of the error, which is the message of the JSON-RPC error object if there is one
+    pub message: String,
+    // full text of the error, for debugging
+    pub raw: String,
+    // true if the connection to the endpoint was refused
+    pub connection_refused: bool,
+}
+
+impl From<&ProbeError> for EndpointError {
+    fn from(err: &ProbeError) -> EndpointError {
+        let (code, message) = match err.json_rpc_error() {
+            Some((code, message)) => (Some(code), message),
+            None => (None, err.to_string()),
+        };
+        EndpointError {
+            category: err.category(),
+            code,
+            message,
+            raw: format!("{:?}", err),
+            connection_refused: err.io_error_kind() == Some(io::ErrorKind::ConnectionRefused),
+        }
+    }
+}
+
+/// EndpointResult stores the result of probing a particular endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointResult {
+    // name of the endpoint
+    pub endpoint: String,
+    // whether the endpoint returned successfully, and if not, how it failed
+    pub outcome: EndpointOutcome,
+    // details of the error returned by the endpoint, if it did not return successfully
+    pub error: Option<EndpointError>,
+    // wall-clock time taken by the call, in milliseconds, or None if the endpoint was not called
+    pub latency_ms: Option<f64>,
+    // true if the endpoint returned successfully, but exceeded its latency warning threshold
+    pub slow: bool,
+    // why the endpoint was not called, if it was skipped
+    pub skip_reason: Option<String>,
+}
+
 /// ProbeResult stores the results of probing a particular microservice
+#[derive(Serialize)]
 pub struct ProbeResult {
     // string of the name of the service
     pub microservice: String,
+    // string of the name of the systemd unit of the service
+    pub unit: String,
     // string of the version of this service currently installed
     pub version: String,
-    // vector of names of endpoints which had errors
-    pub failures: Vec<String>,
-    // vector of names of endpoints which returned successfully
-    pub successes: Vec<String>,
+    // why the installed version could not be read, if it could not
+    pub version_error: Option<VersionError>,
+    // string of the version reported by the running service, if it can be asked for its version
+    pub running_version: Option<String>,
+    // description of why the running service is not running the installed version, if it is not
+    pub version_mismatch: Option<String>,
+    // descriptions of the ways the installed version is outdated or incompatible with other services
+    pub incompatibilities: Vec<String>,
+    // vector of the results of every endpoint which was probed, in the order they were probed
+    pub endpoints: Vec<EndpointResult>,
+    // true if the state changed by mutating endpoints was fully restored, or None if it was not changed
+    pub state_restored: Option<bool>,
     // bool which stores true if the service is running
     pub is_running: bool,
+    // state of the systemd unit of the service, or None if its status was not checked
+    pub unit_status: Option<UnitStatus>,
+    // number of times the unit was restarted within the crash-loop window, or None if its status was not checked
+    pub recent_restarts: Option<u32>,
+    // true if the unit was restarted too many times within the crash-loop window, even if it is running now
+    pub crash_looping: bool,
     // string which stores the tail of the log from journalctl -u service
     pub service_log: Option<String>,
+    // vector of errors encountered by peach-probe itself while probing this service
+    pub probe_errors: Vec<String>,
+    // bool which stores false if problems with this service should not affect the exit code
+    pub required: bool,
+    // vector of suggested fixes for any known problems found while probing this service
+    pub suggestions: Vec<Suggestion>,
+    // vector of commands run by --fix before this service was probed again
+    pub applied_fixes: Vec<String>,
 }
 
 impl ProbeResult {
-    fn new(microservice: &str) -> ProbeResult {
+    pub fn new(microservice: &str) -> ProbeResult {
         ProbeResult {
             microservice: microservice.to_string(),
-            failures: Vec::new(),
-            successes: Vec::new(),
+            unit: microservice.to_string(),
+            running_version: None,
+            version_mismatch: None,
+            incompatibilities: Vec::new(),
+            endpoints: Vec::new(),
+            state_restored: None,
             is_running: false,
+            unit_status: None,
+            recent_restarts: None,
+            crash_looping: false,
             version: "".to_string(),
+            version_error: None,
             service_log: None,
+            probe_errors: Vec::new(),
+            required: true,
+            suggestions: Vec::new(),
+            applied_fixes: Vec::new(),
+        }
+    }
+
+    /// get the names of all endpoints with the given outcome
+    pub fn endpoint_names(&self, outcome: EndpointOutcome) -> Vec<&str> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.outcome == outcome)
+            .map(|endpoint| endpoint.endpoint.as_str())
+            .collect()
+    }
+
+    /// get the results of all endpoints which failed, returned invalid responses or timed out
+    pub fn failing_endpoints(&self) -> impl Iterator<Item = &EndpointResult> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.outcome.is_failing())
+    }
+
+    /// get the result of the endpoint with the given name
+    pub fn endpoint(&self, endpoint_name: &str) -> Option<&EndpointResult> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.endpoint == endpoint_name)
+    }
+
+    /// records that an endpoint returned successfully
+    fn add_success(&mut self, endpoint_name: &str, latency: Duration, slow: bool) {
+        self.endpoints.push(EndpointResult {
+            endpoint: endpoint_name.to_string(),
+            outcome: EndpointOutcome::Success,
+            error: None,
+            latency_ms: Some(latency.as_secs_f64() * 1000.0),
+            slow,
+            skip_reason: None,
+        });
+    }
+
+    /// records that an endpoint did not return successfully, along with the error it returned
+    /// timeouts and responses which failed validation are recorded as distinct outcomes
+    fn add_failure(&mut self, endpoint_name: &str, latency: Duration, error: &ProbeError) {
+        let outcome = match error {
+            ProbeError::Timeout { .. } => EndpointOutcome::Timeout,
+            ProbeError::InvalidResponse(_) => EndpointOutcome::InvalidResponse,
+            _ => EndpointOutcome::Failure,
+        };
+        self.endpoints.push(EndpointResult {
+            endpoint: endpoint_name.to_string(),
+            outcome,
+            error: Some(EndpointError::from(error)),
+            latency_ms: Some(latency.as_secs_f64() * 1000.0),
+            slow: false,
+            skip_reason: None,
+        });
+    }
+
+    /// records that a mutating endpoint was not called, and why
+    fn add_skipped(&mut self, endpoint_name: &str, reason: &str) {
+        self.endpoints.push(EndpointResult {
+            endpoint: endpoint_name.to_string(),
+            outcome: EndpointOutcome::Skipped,
+            error: None,
+            latency_ms: None,
+            slow: false,
+            skip_reason: Some(reason.to_string()),
+        });
+    }
+}
+
+/// FieldCheck is the name of a field in a json object, and a predicate which its value must satisfy
+type FieldCheck<'a> = (&'a str, fn(&Value) -> bool);
+
+/// ProbeOutcome summarises the results of all probes, ordered from least to most severe.
+/// The value of each variant is used as the exit code of peach-probe
+/// (exit code 1 is reserved for invalid command line arguments).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProbeOutcome {
+    // all services are running and all endpoints returned successfully
+    Healthy = 0,
+    // all services are running, but some endpoints returned errors, or some versions are not the ones expected
+    EndpointFailures = 2,
+    // some services are not running
+    ServicesOffline = 3,
+    // peach-probe itself encountered an error, so the results may be incomplete
+    ProbeError = 4,
+}
+
+impl ProbeOutcome {
+    /// get the outcome of probing a single microservice
+    pub fn of(result: &ProbeResult) -> ProbeOutcome {
+        if !result.probe_errors.is_empty() {
+            ProbeOutcome::ProbeError
+        } else if !result.required {
+            // problems with optional services are reported, but do not affect the outcome
+            ProbeOutcome::Healthy
+        } else if !result.is_running || result.crash_looping {
+            ProbeOutcome::ServicesOffline
+        } else if result.failing_endpoints().next().is_some()
+            || result.version_mismatch.is_some()
+            || !result.incompatibilities.is_empty()
+        {
+            ProbeOutcome::EndpointFailures
+        } else {
+            ProbeOutcome::Healthy
         }
     }
 }
@@ -45,101 +288,453 @@ impl ProbeResult {
 pub struct PeachProbe {
     pub results: Vec<ProbeResult>,
     pub verbose: bool,
+    // if true, progress messages are printed to stderr so that stdout only contains the report
+    pub progress_on_stderr: bool,
+    // if true, nothing is printed while probing, as in watch and export mode where results are reported another way
+    pub quiet: bool,
+    // base url at which the routes of peach-web are probed
+    pub web_url: String,
+    // path of the dpkg status file, from which installed versions are read
+    pub dpkg_status_path: PathBuf,
+    // if true, endpoints which change the state of a microservice are skipped
+    pub read_only: bool,
+    // if set, mutating endpoints of the service currently being probed are skipped for this reason,
+    // because its state could not be captured so that it can be restored
+    service_skip_reason: Option<String>,
+    // latency thresholds used for endpoints which have no threshold in the service config
+    pub default_latency: LatencyThreshold,
+    // latency thresholds for endpoints of the service currently being probed
+    service_latency: HashMap<String, LatencyThreshold>,
+    // total time allowed for probing the endpoints of each service which has no timeout in its config
+    pub default_service_timeout_ms: Option<u64>,
+    // versions of services which are known to work together, checked once all services are probed
+    pub compatibility: CompatibilityMatrix,
+    // counts of recent restarts of each unit, kept across runs in watch and export mode
+    pub crash_loop: CrashLoopDetector,
+    // total time allowed for probing the endpoints of the service currently being probed
+    service_timeout_ms: Option<u64>,
+    // time by which probing the endpoints of the service currently being probed must finish
+    service_deadline: Option<Instant>,
+    // calls to mutating endpoints which have not finished, including calls which timed out
+    mutating_calls: InFlight,
+    // calls made to each service which have not finished, kept across runs in watch and export mode
+    // so that a service whose calls are still hung is not called again
+    service_calls: Arc<Mutex<HashMap<String, InFlight>>>,
+    // calls made to the service currently being probed which have not finished
+    current_calls: InFlight,
 }
 
 impl PeachProbe {
-    pub fn new(verbose: bool) -> PeachProbe {
+    pub fn new(verbose: bool, progress_on_stderr: bool) -> PeachProbe {
         PeachProbe {
             results: Vec::new(),
             verbose,
+            progress_on_stderr,
+            quiet: false,
+            web_url: DEFAULT_WEB_URL.to_string(),
+            dpkg_status_path: PathBuf::from(DEFAULT_DPKG_STATUS_PATH),
+            read_only: false,
+            service_skip_reason: None,
+            default_latency: LatencyThreshold::default(),
+            service_latency: HashMap::new(),
+            default_service_timeout_ms: None,
+            compatibility: CompatibilityMatrix::default(),
+            crash_loop: CrashLoopDetector::new(3, Duration::from_secs(600)),
+            service_timeout_ms: None,
+            service_deadline: None,
+            mutating_calls: InFlight::default(),
+            service_calls: Arc::new(Mutex::new(HashMap::new())),
+            current_calls: InFlight::default(),
+        }
+    }
+
+    /// get the most severe outcome of all probes which have been run
+    pub fn outcome(&self) -> ProbeOutcome {
+        self.results
+            .iter()
+            .map(ProbeOutcome::of)
+            .max()
+            .unwrap_or(ProbeOutcome::Healthy)
+    }
+
+    /// get the latency thresholds for an endpoint of the service currently being probed
+    fn latency_threshold(&self, endpoint_name: &str) -> LatencyThreshold {
+        match self.service_latency.get(endpoint_name) {
+            Some(threshold) => threshold.or(self.default_latency),
+            None => self.default_latency,
+        }
+    }
+
+    /// get how long to wait for an endpoint, which is limited by both its own timeout
+    /// and the time remaining before the deadline of the service currently being probed
+    /// returns Err if the deadline of the service has already passed
+    fn endpoint_timeout(
+        &self,
+        threshold: &LatencyThreshold,
+    ) -> Result<Option<Duration>, ProbeError> {
+        let endpoint_timeout = threshold.timeout_ms.map(Duration::from_millis);
+        let remaining = match (self.service_deadline, self.service_timeout_ms) {
+            (Some(deadline), Some(timeout_ms)) => {
+                match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if remaining > Duration::from_millis(0) => remaining,
+                    _ => return Err(ProbeError::Timeout { timeout_ms }),
+                }
+            }
+            _ => return Ok(endpoint_timeout),
+        };
+        Ok(Some(match endpoint_timeout {
+            Some(endpoint_timeout) => endpoint_timeout.min(remaining),
+            None => remaining,
+        }))
+    }
+
+    /// helper function which prints a progress message to stdout, or to stderr if stdout is reserved for the report
+    fn print_progress(&self, msg: &str) {
+        if self.quiet {
+            return;
+        }
+        if self.progress_on_stderr {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// helper function which prints a failure or error to stderr, unless the probe is quiet
+    fn print_error(&self, msg: &str) {
+        if !self.quiet {
+            eprintln!("{}", msg);
+        }
+    }
+
+    /// get a new probe with the same settings as this one, used to probe services on another thread
+    fn worker(&self) -> PeachProbe {
+        let mut worker = PeachProbe::new(self.verbose, self.progress_on_stderr);
+        worker.quiet = self.quiet;
+        worker.web_url = self.web_url.clone();
+        worker.dpkg_status_path = self.dpkg_status_path.clone();
+        worker.read_only = self.read_only;
+        worker.default_latency = self.default_latency;
+        worker.default_service_timeout_ms = self.default_service_timeout_ms;
+        worker.service_calls = self.service_calls.clone();
+        worker
+    }
+
+    /// probe all of the given services, running up to `jobs` probes at the same time
+    /// exclusive services are probed one at a time after all other probes have finished,
+    /// and results are stored in the same order as the services were given
+    /// once all services are probed, their results are checked as a whole (see check_results)
+    pub fn probe_services(&mut self, services: &[ServiceConfig], jobs: usize) {
+        if jobs <= 1 {
+            for service in services {
+                self.probe_service(service);
+            }
+        } else {
+            self.probe_services_concurrently(services, jobs);
+        }
+        self.check_results();
+    }
+
+    /// helper function which probes services using up to `jobs` worker threads
+    fn probe_services_concurrently(&mut self, services: &[ServiceConfig], jobs: usize) {
+        // queue of the services which can be probed concurrently, along with their position in services
+        let queue: VecDeque<(usize, ServiceConfig)> = services
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|(_, service)| !service.is_exclusive())
+            .collect();
+        let mut results: Vec<Option<ProbeResult>> = services.iter().map(|_| None).collect();
+
+        let num_workers = jobs.min(queue.len());
+        let queue = Arc::new(Mutex::new(queue));
+        let (sender, receiver) = mpsc::channel();
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| {
+                let queue = queue.clone();
+                let sender = sender.clone();
+                let mut worker = self.worker();
+                thread::spawn(move || loop {
+                    let next = match queue.lock() {
+                        Ok(mut queue) => queue.pop_front(),
+                        Err(_) => None,
+                    };
+                    match next {
+                        Some((index, service)) => {
+                            let result = worker.probe(&service);
+                            if sender.send((index, result)).is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        for (index, result) in receiver {
+            results[index] = Some(result);
+        }
+        for worker in workers {
+            if worker.join().is_err() {
+                eprintln!("error: a probe worker panicked");
+            }
+        }
+
+        // services with side effects are probed while no other probes are running
+        for (index, service) in services.iter().enumerate() {
+            if service.is_exclusive() {
+                results[index] = Some(self.probe(service));
+            }
+        }
+
+        // services whose worker panicked have no result, which is recorded as an error of peach-probe
+        for (result, service) in results.into_iter().zip(services) {
+            let result = result.unwrap_or_else(|| {
+                let mut result = ProbeResult::new(&service.name);
+                result.required = service.required;
+                result
+                    .probe_errors
+                    .push("probe panicked before returning a result".to_string());
+                result
+            });
+            self.results.push(result);
+        }
+    }
+
+    /// probe any service in the registry and save the result
+    pub fn probe_service(&mut self, service: &ServiceConfig) {
+        let result = self.probe(service);
+        self.results.push(result);
+    }
+
+    /// probe a service again, replacing the result at the given index
+    pub fn reprobe_service(&mut self, index: usize, service: &ServiceConfig) {
+        let mut result = self.probe(service);
+        // only the reprobed service is checked for crash-looping again, so that the restarts of the other
+        // services are not read from the journal again and sampled twice in the same run
+        self.crash_loop.check(std::slice::from_mut(&mut result));
+        self.results[index] = result;
+        self.check_compatibility();
+    }
+
+    /// helper function which runs the checks which need the results of every service or of previous runs:
+    /// counting recent restarts, then checking versions (see check_compatibility)
+    fn check_results(&mut self) {
+        self.crash_loop.check(&mut self.results);
+        self.check_compatibility();
+    }
+
+    /// helper function which checks versions against the compatibility matrix, which needs the results of
+    /// every service, then suggests fixes for any known problems found with each service
+    fn check_compatibility(&mut self) {
+        compat::check(&self.compatibility, &mut self.results);
+        for result in self.results.iter_mut() {
+            result.suggestions = remediation::suggest(result);
         }
     }
 
-    /// probe any microservice, using systemctl status to see if the service is running
-    /// and testing endpoints for services which support this (peach-stats, peach-network, peach-oled)
-    /// for all other microservices this function just checks if the service is running
-    pub fn probe_service(&mut self, service: Microservice) {
-        // get package name from enum
-        let service_name = Microservice::get_package_name(&service);
-        println!("[ probing {} ]", service_name);
+    /// probe any service in the registry, using systemctl show to see if the service is running
+    /// and testing the endpoints or routes which the service exposes, if it has a built-in probe
+    fn probe(&mut self, service: &ServiceConfig) -> ProbeResult {
+        let service_name = &service.name;
+        let unit_name = service.unit_name();
+        self.print_progress(&format!("[ probing {} ]", service_name));
 
         // instantiate ProbeResult
-        let mut result = ProbeResult::new(&service_name);
+        let mut result = ProbeResult::new(service_name);
+        result.unit = unit_name.to_string();
+        result.required = service.required;
+        self.service_latency = service.latency.clone();
+        self.service_skip_reason = None;
 
         // get version of service
-        result.version = PeachProbe::get_service_version(&service_name);
+        if service.runs(Check::Version) {
+            self.get_service_version(service_name, &mut result);
+        } else {
+            result.version = "Unchecked".to_string();
+        }
 
-        // check status of service
-        let status_result = PeachProbe::get_service_status(&service_name);
+        // check status of service, assuming it is running if status is not checked
+        let status_result = if service.runs(Check::Status) {
+            PeachProbe::get_service_status(unit_name).map(Some)
+        } else {
+            Ok(None)
+        };
         match status_result {
-            Ok(is_running) => {
+            Ok(unit_status) => {
+                let is_running = match &unit_status {
+                    Some(unit_status) => unit_status.is_running(),
+                    None => true,
+                };
                 result.is_running = is_running;
+                result.unit_status = unit_status;
                 // if the service is not running, get the journalctl log of the service
                 if !is_running {
-                    let log_result = PeachProbe::get_service_log(&service_name);
+                    let log_result = PeachProbe::get_service_log(unit_name);
                     match log_result {
                         Ok(log) => {
                             result.service_log = Some(log);
                         }
                         Err(err) => {
-                            eprintln!("error getting log for {}: {:#?}", service_name, err);
+                            self.print_error(&format!(
+                                "error getting log for {}: {:#?}",
+                                service_name, err
+                            ));
+                            result
+                                .probe_errors
+                                .push(format!("error getting log: {:?}", err));
                         }
                     }
                 }
             }
             Err(err) => {
                 result.is_running = false;
-                eprintln!(
+                self.print_error(&format!(
                     "error retrieving service status of {}: {:#?}",
                     service_name, err
-                );
+                ));
+                result
+                    .probe_errors
+                    .push(format!("error retrieving service status: {:?}", err));
             }
         }
 
-        // probe endpoints for the serivce if applicable
-        let result = match service {
-            Microservice::Peach_Stats => self.peach_stats(result),
-            Microservice::Peach_Oled => self.peach_oled(result),
-            Microservice::Peach_Network => self.peach_network(result),
-            _ => {
-                info!("probing endpoints not implemented for this service");
-                result
+        // a service which did not return calls made in a previous run is not called again,
+        // so that a hung service does not leave more and more calls running in the background
+        self.current_calls = self.calls_to(service_name);
+        let outstanding = self.current_calls.count();
+        if outstanding > 0 {
+            self.print_error(&format!(
+                "++ {} calls to {} from a previous run have still not returned, so it was not called again",
+                outstanding, service_name
+            ));
+            result.probe_errors.push(format!(
+                "{} calls from a previous run have still not returned, so the service was not called again",
+                outstanding
+            ));
+            return result;
+        }
+
+        // check the running service is running the installed version
+        if result.is_running && service.runs(Check::Version) {
+            self.check_running_version(service, &mut result);
+        }
+
+        // probe endpoints for the serivce, using its contract if it has one
+        self.service_timeout_ms = service.timeout_ms.or(self.default_service_timeout_ms);
+        self.service_deadline = self
+            .service_timeout_ms
+            .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
+        let result = if !service.runs(Check::Endpoints) {
+            result
+        } else if service.contract.is_some() {
+            self.probe_contract(service, result)
+        } else {
+            match service.builtin_probe() {
+                Some(Microservice::Peach_Stats) => self.peach_stats(result),
+                Some(Microservice::Peach_Oled) => self.peach_oled(result),
+                Some(Microservice::Peach_Network) => self.peach_network(result),
+                Some(Microservice::Peach_Buttons) => self.peach_buttons(result),
+                Some(Microservice::Peach_Menu) => self.peach_menu(unit_name, result),
+                Some(Microservice::Peach_Web) => self.peach_web(result),
+                Some(Microservice::Peach_Monitor) => self.peach_monitor(result),
+                None => {
+                    info!("no endpoints to probe for {}", service_name);
+                    result
+                }
             }
         };
 
-        // save result
-        self.results.push(result);
+        result
     }
 
-    /// helper function which gets the version of the microservice running using apt-get
-    fn get_service_version_result(service: &str) -> Result<String, ProbeError> {
-        let output = Command::new("/usr/bin/apt")
-            .arg("list")
-            .arg(service)
-            .output()?;
-        let command_output = std::str::from_utf8(&output.stdout)?;
-        // use a regex to get the version number from the string
-        let re = Regex::new(r".*buster,now (\d+\.\d+\.\d+) arm64.*")?;
-        let cap = re.captures(command_output);
-        match cap {
-            Some(c) => {
-                let version = &c[1];
-                Ok(version.to_string())
+    /// helper function which gets the calls made to a service which have not finished
+    fn calls_to(&self, service: &str) -> InFlight {
+        match self.service_calls.lock() {
+            Ok(mut service_calls) => service_calls
+                .entry(service.to_string())
+                .or_default()
+                .clone(),
+            Err(_) => InFlight::default(),
+        }
+    }
+
+    /// helper function which gets the installed version of the microservice from the dpkg database
+    fn get_service_version_result(&self, service: &str) -> Result<DebianVersion, ProbeError> {
+        dpkg::installed_version(&self.dpkg_status_path, service)
+    }
+
+    /// helper function which checks whether a running service is running its installed version,
+    /// by asking the service for its version if it can be asked, or otherwise by checking that
+    /// the service was started after the package was last installed or upgraded
+    fn check_running_version(&self, service: &ServiceConfig, result: &mut ProbeResult) {
+        let installed: DebianVersion = match result.version.parse() {
+            Ok(version) => version,
+            Err(_) => return,
+        };
+
+        if let (Some(method), Some(address)) = (&service.version_method, service.api_address()) {
+            let method = method.clone();
+            let timeout = self.default_latency.timeout_ms.map(Duration::from_millis);
+            let running = timeout::run_with_timeout(
+                self.current_calls
+                    .track(move || jsonrpc::call(&address, &method, json!([]))),
+                timeout,
+            );
+            match running {
+                Ok(Value::String(running)) => {
+                    // services only know their upstream version, not the debian revision of their package
+                    let matches = running
+                        .parse::<DebianVersion>()
+                        .map(|running| running.upstream == installed.upstream)
+                        .unwrap_or(false);
+                    if !matches {
+                        result.version_mismatch = Some(format!(
+                            "running version {} differs from installed version {}",
+                            running, installed
+                        ));
+                    }
+                    result.running_version = Some(running);
+                    return;
+                }
+                Ok(other) => info!("unexpected version of {}: {}", service.name, other),
+                Err(e) => info!("error getting running version of {}: {}", service.name, e),
             }
-            None => Err(ProbeError::GetServiceVersionRegexMatchError),
+        }
+
+        // the start time is only known if the status of the unit was checked and it has been started since boot
+        let start_time = match result
+            .unit_status
+            .as_ref()
+            .and_then(|unit_status| unit_status.main_start_timestamp)
+        {
+            Some(start_time) => start_time,
+            None => return,
+        };
+        let install_time = dpkg::install_time(&self.dpkg_status_path, &service.name).map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default()
+        });
+        match install_time {
+            Ok(install_time) if install_time > start_time => {
+                result.version_mismatch = Some(format!(
+                    "package was upgraded to {} after the service was started, and the service has not been restarted since",
+                    installed
+                ));
+            }
+            Ok(_) => (),
+            Err(e) => info!(
+                "error comparing start and install time of {}: {}",
+                service.name, e
+            ),
         }
     }
 
-    /// helper function to call systemctl status for service
-    pub fn get_service_status(service: &str) -> Result<bool, ProbeError> {
-        let output = Command::new("/usr/bin/systemctl")
-            .arg("status")
-            .arg(service)
-            .output()?;
-        let status = output.status;
-        // returns true if the service had an exist status of 0 (is running)
-        let is_running = status.success();
-        Ok(is_running)
+    /// helper function to get the state of the systemd unit of a service
+    pub fn get_service_status(service: &str) -> Result<UnitStatus, ProbeError> {
+        systemd::unit_status(service)
     }
 
     /// helper function to get last 2 lines of journalctl log for service
@@ -156,167 +751,366 @@ impl PeachProbe {
         Ok(log_output)
     }
 
-    /// helper function which gets the version of the microservice running using apt-get as a string
-    /// if there is an error getting the version, it returns the string "Unknown"
-    fn get_service_version(service: &str) -> String {
-        let version_result = PeachProbe::get_service_version_result(service);
+    /// helper function which records the installed version of the microservice in its result as a string
+    /// if there is an error getting the version, it records the string "Unknown" and the kind of error
+    fn get_service_version(&self, service: &str, result: &mut ProbeResult) {
+        let version_result = self.get_service_version_result(service);
         match version_result {
-            Ok(version) => version,
-            Err(_) => "Unknown".to_string(),
+            Ok(version) => result.version = version.to_string(),
+            Err(e) => {
+                info!("error getting version of {}: {}", service, e);
+                result.version = "Unknown".to_string();
+                result.version_error = Some(VersionError::of(&e));
+            }
         }
     }
 
     /// helper function for probing an endpoint on a peach microservice and collecting errors for a final report
-    fn probe_peach_endpoint<T>(
+    /// the endpoint is called by the given function, which is timed to record the latency of the endpoint
+    /// returns the value returned by the endpoint, if it returned successfully
+    /// if the endpoint does not return before its timeout, it is recorded as timed out and the probe moves on
+    fn probe_peach_endpoint<T, E, F>(
         &mut self,
-        endpoint_result: Result<T, PeachError>,
+        call: F,
         endpoint_name: &str,
         result: &mut ProbeResult,
-    ) {
+    ) -> Option<T>
+    where
+        T: Send + 'static,
+        E: Into<ProbeError>,
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        let threshold = self.latency_threshold(endpoint_name);
+        let start = Instant::now();
+        let endpoint_result = self
+            .endpoint_timeout(&threshold)
+            .and_then(|timeout| timeout::run_with_timeout(self.current_calls.track(call), timeout));
+        let latency = start.elapsed();
+
+        // endpoints which return successfully, but too slowly, are treated as failures
+        let endpoint_result = endpoint_result.and_then(|value| {
+            if threshold.is_failure(latency) {
+                Err(ProbeError::LatencyExceeded {
+                    latency_ms: latency.as_millis() as u64,
+                    threshold_ms: threshold.fail_ms.unwrap_or_default(),
+                })
+            } else {
+                Ok(value)
+            }
+        });
+
         match endpoint_result {
-            Ok(_) => {
-                if self.verbose {
-                    println!("++ {} endpoint is online", endpoint_name);
+            Ok(value) => {
+                let slow = threshold.is_warning(latency);
+                if slow {
+                    self.print_error(&format!(
+                        "++ {} endpoint is online but slow ({}ms)",
+                        endpoint_name,
+                        latency.as_millis()
+                    ));
+                } else if self.verbose {
+                    self.print_progress(&format!(
+                        "++ {} endpoint is online ({}ms)",
+                        endpoint_name,
+                        latency.as_millis()
+                    ));
                 }
-                result.successes.push(endpoint_name.to_string());
+                result.add_success(endpoint_name, latency, slow);
+                Some(value)
             }
             Err(e) => {
-                eprintln!("++ {} endpoint is offline", endpoint_name);
-                match e {
-                    PeachError::JsonRpcHttp(e) => {
-                        eprintln!("Returned JsonRpcHTTP error: {:#?}\n", e)
-                    }
-                    PeachError::JsonRpcCore(e) => {
-                        eprintln!("Returned JsonRpcCore error: {:#?}\n", e)
-                    }
-                    PeachError::Serde(_) => eprintln!("Returned Serde Json serialization error\n"),
+                match &e {
+                    ProbeError::Timeout { timeout_ms } => self.print_error(&format!(
+                        "++ {} endpoint timed out after {}ms",
+                        endpoint_name, timeout_ms
+                    )),
+                    ProbeError::InvalidResponse(violation) => self.print_error(&format!(
+                        "++ {} endpoint is online but returned an invalid response\nReturned invalid response: {}\n",
+                        endpoint_name, violation
+                    )),
+                    _ => self.print_error(&format!(
+                        "++ {} endpoint is offline\nReturned {}\n",
+                        endpoint_name, e
+                    )),
                 }
-                result.failures.push(endpoint_name.to_string());
+                result.add_failure(endpoint_name, latency, &e);
+                None
+            }
+        }
+    }
+
+    /// helper function which, if the probe is read-only, records that a mutating endpoint was skipped
+    /// returns true if the endpoint should not be called
+    fn skip_mutating_endpoint(&self, endpoint_name: &str, result: &mut ProbeResult) -> bool {
+        let reason = if self.read_only {
+            "mutating endpoint skipped in read-only mode"
+        } else {
+            match &self.service_skip_reason {
+                Some(reason) => reason,
+                None => return false,
             }
+        };
+        self.print_progress(&format!(
+            "++ {} endpoint was skipped: {}",
+            endpoint_name, reason
+        ));
+        result.add_skipped(endpoint_name, reason);
+        true
+    }
+
+    /// helper function for probing an endpoint on a peach microservice which changes its state,
+    /// which is skipped if the probe is read-only
+    fn probe_mutating_endpoint<T, E, F>(
+        &mut self,
+        call: F,
+        endpoint_name: &str,
+        result: &mut ProbeResult,
+    ) -> Option<T>
+    where
+        T: Send + 'static,
+        E: Into<ProbeError>,
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        if self.skip_mutating_endpoint(endpoint_name, result) {
+            return None;
         }
+        let call = self.mutating_calls.track(call);
+        self.probe_peach_endpoint(call, endpoint_name, result)
+    }
+
+    /// helper function for probing an endpoint on a peach microservice and validating its response,
+    /// recording responses which fail validation separately from endpoints which returned errors
+    fn probe_validated_endpoint<T, E, F>(
+        &mut self,
+        call: F,
+        endpoint_name: &str,
+        validator: Validator,
+        result: &mut ProbeResult,
+    ) where
+        T: serde::Serialize,
+        E: Into<ProbeError>,
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        let validated_call = move || {
+            let value = call().map_err(Into::into)?;
+            let value = serde_json::to_value(&value)
+                .map_err(|e| ProbeError::InvalidResponse(e.to_string()))?;
+            validator(&value).map_err(ProbeError::InvalidResponse)
+        };
+        self.probe_peach_endpoint(validated_call, endpoint_name, result);
     }
 
     /// helper function for probing an endpoint on a peach microservice which expects a particular JsonRPCCore Error
-    fn probe_assert_error_endpoint<T>(
+    fn probe_assert_error_endpoint<T, F>(
         &mut self,
-        endpoint_result: Result<T, PeachError>,
+        call: F,
         endpoint_name: &str,
         expected_error_code: i64,
         result: &mut ProbeResult,
-    ) {
-        match endpoint_result {
+    ) where
+        F: FnOnce() -> Result<T, PeachError> + Send + 'static,
+    {
+        let quiet = self.quiet;
+        let asserted_call = move || match call() {
             Ok(_) => {
-                eprintln!("++ this endpoint should not return successfully during peach-probe, something is strange");
-                result.failures.push(endpoint_name.to_string());
+                if !quiet {
+                    eprintln!("++ this endpoint should not return successfully during peach-probe, something is strange");
+                }
+                Err(ProbeError::UnexpectedResponse(format!(
+                    "success, but expected JsonRpcCore error with code {}",
+                    expected_error_code
+                )))
             }
-            Err(e) => {
-                match e {
-                    PeachError::JsonRpcCore(e) => {
-                        match e.kind() {
-                            // this is the expected error, all other errors are unexpected
-                            jsonrpc_client_core::ErrorKind::JsonRpcError(err) => {
-                                if err.code.code() == expected_error_code {
-                                    if self.verbose {
-                                        println!("++ {} endpoint is online", endpoint_name);
-                                    }
-                                    result.successes.push(endpoint_name.to_string());
-                                } else {
-                                    eprintln!("++ {} endpoint is offline", endpoint_name);
-                                    eprintln!("Returned JsonRpcCore error with unexpected code or message: {:#?}\n", e);
-                                    result.failures.push(endpoint_name.to_string());
-                                }
-                            }
-                            _ => {
-                                eprintln!("++ {} endpoint is offline", endpoint_name);
-                                eprintln!("Returned unexpected JsonRpcCore error: {:#?}\n", e);
-                                result.failures.push(endpoint_name.to_string());
-                            }
-                        }
-                    }
-                    PeachError::JsonRpcHttp(e) => {
-                        eprintln!("++ {} endpoint is offline", endpoint_name);
-                        eprintln!("Returned JsonRpcHTTP error: {:#?}\n", e);
-                        result.failures.push(endpoint_name.to_string());
+            Err(PeachError::JsonRpcCore(e)) => {
+                // this is the expected error, all other errors are unexpected
+                let unexpected_error = match e.kind() {
+                    jsonrpc_client_core::ErrorKind::JsonRpcError(err)
+                        if err.code.code() == expected_error_code =>
+                    {
+                        return Ok(());
                     }
-                    PeachError::Serde(_) => {
-                        eprintln!("++ {} endpoint is offline", endpoint_name);
-                        eprintln!("Returned Serde Json serialization error\n");
-                        result.failures.push(endpoint_name.to_string());
+                    jsonrpc_client_core::ErrorKind::JsonRpcError(err) => {
+                        Some((err.code.code(), err.message.clone()))
                     }
+                    _ => None,
+                };
+                match unexpected_error {
+                    Some((code, message)) => Err(ProbeError::JsonRpcError {
+                        code,
+                        message: format!(
+                            "{} (expected error code {})",
+                            message, expected_error_code
+                        ),
+                    }),
+                    None => Err(ProbeError::from(PeachError::JsonRpcCore(e))),
                 }
             }
-        }
+            Err(e) => Err(ProbeError::from(e)),
+        };
+        self.probe_peach_endpoint(asserted_call, endpoint_name, result);
     }
 
     /// probes all endpoints on the peach-stats microservice
     pub fn peach_stats(&mut self, mut result: ProbeResult) -> ProbeResult {
-        // probe endpoints
-        self.probe_peach_endpoint(
-            stats_client::cpu_stats_percent(),
+        // probe endpoints, validating their responses
+        self.probe_validated_endpoint(
+            stats_client::cpu_stats_percent,
             "cpu_stats_percent",
+            validate::cpu_stats_percent,
+            &mut result,
+        );
+        self.probe_validated_endpoint(
+            stats_client::load_average,
+            "load_average",
+            validate::load_average,
+            &mut result,
+        );
+        self.probe_validated_endpoint(
+            stats_client::disk_usage,
+            "disk_usage",
+            validate::disk_usage,
+            &mut result,
+        );
+        self.probe_validated_endpoint(
+            stats_client::mem_stats,
+            "mem_stats",
+            validate::mem_stats,
+            &mut result,
+        );
+        self.probe_validated_endpoint(stats_client::ping, "ping", validate::ping, &mut result);
+        self.probe_validated_endpoint(
+            stats_client::uptime,
+            "uptime",
+            validate::uptime,
             &mut result,
         );
-        self.probe_peach_endpoint(stats_client::load_average(), "load_average", &mut result);
-        self.probe_peach_endpoint(stats_client::disk_usage(), "disk_usage", &mut result);
-        self.probe_peach_endpoint(stats_client::mem_stats(), "mem_stats", &mut result);
-        self.probe_peach_endpoint(stats_client::ping(), "ping", &mut result);
-        self.probe_peach_endpoint(stats_client::uptime(), "uptime", &mut result);
 
         // save result
         result
     }
 
     /// probes all endpoints on peach-network microservice
+    /// the network state of the device is captured before any mutating endpoints are called, and restored afterwards
     pub fn peach_network(&mut self, mut result: ProbeResult) -> ProbeResult {
+        // if the network state cannot be captured, it could not be restored, so mutating endpoints are skipped
+        // and the failure is recorded as an error of peach-probe, since the probe was not as thorough as asked
+        let snapshot = if self.read_only {
+            None
+        } else {
+            let timeout = self.default_latency.timeout_ms.map(Duration::from_millis);
+            match NetworkSnapshot::capture(timeout, &self.current_calls) {
+                Ok(snapshot) => Some(snapshot.guard()),
+                Err(e) => {
+                    let error = format!(
+                        "error capturing network state, so mutating endpoints were skipped: {}",
+                        e
+                    );
+                    self.print_error(&error);
+                    result.probe_errors.push(error);
+                    self.service_skip_reason = Some(format!(
+                        "mutating endpoint skipped because the network state could not be captured: {}",
+                        e
+                    ));
+                    None
+                }
+            }
+        };
+
         // probe endpoints which should successfully return if online
-        self.probe_peach_endpoint(
-            network_client::add("peach-probe-test-ssid", "peach-probe-test-pass"),
+        self.probe_mutating_endpoint(
+            || network_client::add("peach-probe-test-ssid", "peach-probe-test-pass"),
             "add",
             &mut result,
         );
-        self.probe_peach_endpoint(
-            network_client::available_networks("wlan0"),
+        self.probe_validated_endpoint(
+            || network_client::available_networks("wlan0"),
             "available_networks",
+            validate::networks,
             &mut result,
         );
-        self.probe_peach_endpoint(
-            network_client::id("wlan0", "peach-probe-test-ssid"),
+        self.probe_validated_endpoint(
+            || network_client::id("wlan0", "peach-probe-test-ssid"),
             "id",
+            validate::network_id,
             &mut result,
         );
-        self.probe_peach_endpoint(network_client::ip("wlan0"), "ip", &mut result);
-        self.probe_peach_endpoint(network_client::ssid("wlan0"), "ssid", &mut result);
-        self.probe_peach_endpoint(network_client::ping(), "ping", &mut result);
-        self.probe_peach_endpoint(network_client::reconfigure(), "reconfigure", &mut result);
-        self.probe_peach_endpoint(
-            network_client::saved_networks(),
+        self.probe_validated_endpoint(
+            || network_client::ip("wlan0"),
+            "ip",
+            validate::ip,
+            &mut result,
+        );
+        self.probe_validated_endpoint(
+            || network_client::ssid("wlan0"),
+            "ssid",
+            validate::ssid,
+            &mut result,
+        );
+        self.probe_validated_endpoint(network_client::ping, "ping", validate::ping, &mut result);
+        self.probe_mutating_endpoint(network_client::reconfigure, "reconfigure", &mut result);
+        self.probe_validated_endpoint(
+            network_client::saved_networks,
             "saved_networks",
+            validate::networks,
             &mut result,
         );
-        self.probe_peach_endpoint(network_client::state("wlan0"), "state", &mut result);
-        self.probe_peach_endpoint(network_client::traffic("wlan0"), "traffic", &mut result);
-        self.probe_peach_endpoint(
-            network_client::forget("wlan0", "peach-probe-test-ssid"),
+        self.probe_validated_endpoint(
+            || network_client::state("wlan0"),
+            "state",
+            validate::state,
+            &mut result,
+        );
+        self.probe_validated_endpoint(
+            || network_client::traffic("wlan0"),
+            "traffic",
+            validate::traffic,
+            &mut result,
+        );
+        self.probe_mutating_endpoint(
+            || network_client::forget("wlan0", "peach-probe-test-ssid"),
             "forget",
             &mut result,
         );
 
         // if online, the following functions should return an error which we should catch and confirm
-        self.probe_assert_error_endpoint(
-            network_client::connect("peach-probe-test-ssid", "wlan0"),
-            "connect",
-            -32027,
-            &mut result,
-        );
+        // connect is treated as mutating, since it would switch networks if it succeeded
+        if !self.skip_mutating_endpoint("connect", &mut result) {
+            self.probe_assert_error_endpoint(
+                self.mutating_calls
+                    .track(|| network_client::connect("peach-probe-test-ssid", "wlan0")),
+                "connect",
+                -32027,
+                &mut result,
+            );
+        }
 
         // probe switching between ap and client mode
-        self.probe_peach_endpoint(network_client::activate_ap(), "activate_ap", &mut result);
-        self.probe_peach_endpoint(
-            network_client::activate_client(),
+        self.probe_mutating_endpoint(network_client::activate_ap, "activate_ap", &mut result);
+        self.probe_mutating_endpoint(
+            network_client::activate_client,
             "activate_client",
             &mut result,
         );
 
+        // restore the network state to how it was before probing, once any mutating calls which timed out
+        // have finished, since a call which is still running could change the state again after it is restored
+        if let Some(snapshot) = snapshot {
+            self.print_progress("[ restoring network state ]");
+            let mut problems = Vec::new();
+            if !self.mutating_calls.wait(MUTATING_CALL_WAIT) {
+                problems.push(format!(
+                    "a mutating call to peach-network was still running after waiting {}s, so it may change the network state after it was restored",
+                    MUTATING_CALL_WAIT.as_secs()
+                ));
+            }
+            problems.extend(snapshot.restore());
+            result.state_restored = Some(problems.is_empty());
+            for problem in problems {
+                self.print_error(&format!("error restoring network state: {}", problem));
+                result.probe_errors.push(problem);
+            }
+        }
+
         // return result
         result
     }
@@ -324,32 +1118,327 @@ impl PeachProbe {
     /// probes all endpoints on the peach-oled microservice
     pub fn peach_oled(&mut self, mut result: ProbeResult) -> ProbeResult {
         // probe endpoints
-        self.probe_peach_endpoint(oled_client::ping(), "ping", &mut result);
+        self.probe_validated_endpoint(oled_client::ping, "ping", validate::ping, &mut result);
 
         // probe clear and flush
-        self.probe_peach_endpoint(oled_client::clear(), "clear", &mut result);
-        self.probe_peach_endpoint(
-            oled_client::write(0, 0, "peach-probe success", "6x8"),
+        self.probe_mutating_endpoint(oled_client::clear, "clear", &mut result);
+        self.probe_mutating_endpoint(
+            || oled_client::write(0, 0, "peach-probe success", "6x8"),
             "write",
             &mut result,
         );
 
         // probe draw endpoint
         let bytes = PEACH_LOGO.to_vec();
-        self.probe_peach_endpoint(
-            oled_client::draw(bytes, 64, 64, 32, 10),
+        self.probe_mutating_endpoint(
+            move || oled_client::draw(bytes, 64, 64, 32, 10),
             "draw",
             &mut result,
         );
 
         // just clear at the end without flush so that state of peach-oled is not changed
-        self.probe_peach_endpoint(oled_client::flush(), "flush", &mut result);
+        self.probe_mutating_endpoint(oled_client::flush, "flush", &mut result);
 
         // test power off endpoint
-        self.probe_peach_endpoint(oled_client::power(false), "power-off", &mut result);
-        self.probe_peach_endpoint(oled_client::power(true), "power-on", &mut result);
+        self.probe_mutating_endpoint(|| oled_client::power(false), "power-off", &mut result);
+        self.probe_mutating_endpoint(|| oled_client::power(true), "power-on", &mut result);
 
         // return result
         result
     }
+
+    /// probes the json-rpc pubsub interface of the peach-buttons microservice
+    pub fn peach_buttons(&mut self, mut result: ProbeResult) -> ProbeResult {
+        // all other endpoints are called over the same websocket connection, which is shared
+        // with the threads making each call so that a hung call does not block the probe
+        let client = match self.probe_peach_endpoint(ButtonsClient::connect, "connect", &mut result)
+        {
+            Some(client) => Arc::new(Mutex::new(client)),
+            None => return result,
+        };
+
+        let ping_client = client.clone();
+        self.probe_peach_endpoint(
+            move || lock_client(&ping_client)?.ping(),
+            "ping",
+            &mut result,
+        );
+
+        // probe the subscription handshake, unsubscribing again so that no state is left behind
+        let subscribe_client = client.clone();
+        let subscription_id = self.probe_peach_endpoint(
+            move || lock_client(&subscribe_client)?.subscribe_buttons(),
+            "subscribe_buttons",
+            &mut result,
+        );
+        if let Some(subscription_id) = subscription_id {
+            let unsubscribe_client = client.clone();
+            self.probe_peach_endpoint(
+                move || lock_client(&unsubscribe_client)?.unsubscribe_buttons(&subscription_id),
+                "unsubscribe_buttons",
+                &mut result,
+            );
+        }
+
+        // the connection is still in use if any call timed out, in which case it is left to close itself
+        if let Ok(client) = Arc::try_unwrap(client) {
+            if let Err(e) = client
+                .into_inner()
+                .map_err(|_| ProbeError::UnexpectedResponse("connection poisoned".to_string()))
+                .and_then(ButtonsClient::close)
+            {
+                info!("error closing connection to peach-buttons: {}", e);
+            }
+        }
+
+        // return result
+        result
+    }
+
+    /// probes the peach-menu microservice, which has no api of its own,
+    /// by checking that it is connected to peach-buttons and that peach-buttons and peach-oled
+    /// are reachable at the addresses peach-menu is configured to use
+    pub fn peach_menu(&mut self, unit_name: &str, mut result: ProbeResult) -> ProbeResult {
+        // read the environment of the running peach-menu process, falling back to the defaults
+        // used by peach-lib if its environment variables are not set
+        let pid = result
+            .unit_status
+            .as_ref()
+            .and_then(|unit_status| unit_status.main_pid)
+            .ok_or_else(|| ProbeError::ProcessError(format!("{} has no main process", unit_name)));
+        let env = match &pid {
+            Ok(pid) => match process::environment(*pid) {
+                Ok(env) => env,
+                Err(e) => {
+                    // the environment of another user's process can only be read as root
+                    self.print_error(&format!(
+                        "error reading the environment of peach-menu, so the default addresses were probed: {}",
+                        e
+                    ));
+                    result.probe_errors.push(format!(
+                        "error reading the environment of peach-menu, so the default addresses were probed: {}",
+                        e
+                    ));
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+        let buttons_address = env
+            .get("PEACH_BUTTONS_SERVER")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_BUTTONS_SERVER.to_string());
+        let oled_address = env
+            .get("PEACH_OLED_SERVER")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_OLED_SERVER.to_string());
+
+        // probe the dependencies of peach-menu
+        let address = buttons_address.clone();
+        self.probe_peach_endpoint(
+            move || ButtonsClient::connect_to(&address).and_then(|mut client| client.ping()),
+            "buttons_ping",
+            &mut result,
+        );
+        self.probe_peach_endpoint(
+            move || jsonrpc::call(&oled_address, "ping", json!([])),
+            "oled_ping",
+            &mut result,
+        );
+
+        // peach-menu holds a websocket connection to peach-buttons open while it is listening
+        // for button presses, so check that this connection is established
+        let buttons_port = buttons_address
+            .rsplit(':')
+            .next()
+            .and_then(|port| port.parse::<u16>().ok())
+            .ok_or_else(|| {
+                ProbeError::ConfigError(format!(
+                    "peach-buttons address {} has no valid port",
+                    buttons_address
+                ))
+            });
+        // the open sockets of another user's process can only be read as root, in which case
+        // the connection cannot be checked, which is not a failure of peach-menu
+        let inspectable = match &pid {
+            Ok(pid) => process::can_inspect(*pid),
+            Err(_) => Ok(()),
+        };
+        match inspectable {
+            Err(e) if process::is_permission_denied(&e) => {
+                self.print_error(&format!(
+                    "++ buttons_subscription was not checked, since the connections of peach-menu could not be read: {}",
+                    e
+                ));
+                result.probe_errors.push(format!(
+                    "buttons_subscription was not checked, since the connections of peach-menu could not be read (run peach-probe as root): {}",
+                    e
+                ));
+            }
+            _ => {
+                let subscription = move || {
+                    if process::is_connected_to_port(pid?, buttons_port?)? {
+                        Ok(())
+                    } else {
+                        Err(ProbeError::ProcessError(format!(
+                            "peach-menu has no connection to peach-buttons at {}",
+                            buttons_address
+                        )))
+                    }
+                };
+                self.probe_peach_endpoint(subscription, "buttons_subscription", &mut result);
+            }
+        }
+
+        // return result
+        result
+    }
+
+    /// probes the http routes of the peach-web microservice, checking that each returns
+    /// the expected status code and content type
+    pub fn peach_web(&mut self, mut result: ProbeResult) -> ProbeResult {
+        let (address, prefix) = match http_client::split_url(&self.web_url) {
+            Ok(split) => split,
+            Err(e) => {
+                result.probe_errors.push(e.to_string());
+                return result;
+            }
+        };
+
+        for (path, expected_status, expected_content_type) in WEB_ROUTES.iter() {
+            let address = address.clone();
+            let url_path = format!("{}{}", prefix, path);
+            let route = move || {
+                http_client::get(&address, &url_path).and_then(|response| {
+                    let content_type = response.header("content-type").unwrap_or_default();
+                    if response.status == *expected_status
+                        && content_type.contains(expected_content_type)
+                    {
+                        Ok(())
+                    } else {
+                        Err(ProbeError::UnexpectedResponse(format!(
+                            "expected status {} with content type {}, got status {} with content type {}",
+                            expected_status, expected_content_type, response.status, content_type
+                        )))
+                    }
+                })
+            };
+            self.probe_peach_endpoint(route, path, &mut result);
+        }
+
+        // return result
+        result
+    }
+
+    /// helper function which reads a json file and checks that it is an object containing
+    /// all the given fields, where each field is checked with the given predicate
+    fn read_json_fields(path: &str, fields: &[FieldCheck]) -> Result<Value, ProbeError> {
+        let contents = fs::read_to_string(path)?;
+        let value: Value = serde_json::from_str(&contents)?;
+        for (field, is_valid) in fields {
+            match value.get(field) {
+                Some(field_value) if is_valid(field_value) => (),
+                _ => {
+                    return Err(ProbeError::InvalidResponse(format!(
+                        "invalid or missing field {} in {}",
+                        field, path
+                    )))
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// probes the peach-monitor microservice, which has no api of its own,
+    /// by checking the data usage and alert threshold files it maintains
+    /// the network traffic it reads is probed as part of peach-network, so it is not called again here
+    pub fn peach_monitor(&mut self, mut result: ProbeResult) -> ProbeResult {
+        // the total data usage is periodically written to disk by peach-monitor
+        self.probe_peach_endpoint(
+            || PeachProbe::read_json_fields(MONITOR_DATA_PATH, &[("total", Value::is_u64)]),
+            "usage_data",
+            &mut result,
+        );
+
+        // alert thresholds are set by the user, and the alert flags are set by peach-monitor
+        // when data usage exceeds those thresholds
+        let thresholds = || {
+            PeachProbe::read_json_fields(
+                MONITOR_THRESHOLD_PATH,
+                &[
+                    ("warn", Value::is_u64),
+                    ("cut", Value::is_u64),
+                    ("warn_flag", Value::is_boolean),
+                    ("cut_flag", Value::is_boolean),
+                ],
+            )
+            .and_then(|thresholds| {
+                // a warning should never be triggered after the network has been cut
+                if thresholds["warn"].as_u64() <= thresholds["cut"].as_u64() {
+                    Ok(thresholds)
+                } else {
+                    Err(ProbeError::InvalidResponse(format!(
+                        "warn threshold is greater than cut threshold in {}",
+                        MONITOR_THRESHOLD_PATH
+                    )))
+                }
+            })
+        };
+        self.probe_peach_endpoint(thresholds, "alert_thresholds", &mut result);
+
+        // return result
+        result
+    }
+
+    /// probes the endpoints of a service using the JSON-RPC contract given in its config
+    pub fn probe_contract(
+        &mut self,
+        service: &ServiceConfig,
+        mut result: ProbeResult,
+    ) -> ProbeResult {
+        let contract = service
+            .contract
+            .as_ref()
+            .ok_or_else(|| ProbeError::ConfigError("no contract given".to_string()))
+            .and_then(|path| Contract::load(path));
+        let contract = match contract {
+            Ok(contract) => contract,
+            Err(e) => {
+                result.probe_errors.push(e.to_string());
+                return result;
+            }
+        };
+        let address = match service.api_address() {
+            Some(address) => address,
+            None => {
+                result.probe_errors.push(format!(
+                    "no address configured for {}, which is needed to probe its contract",
+                    service.name
+                ));
+                return result;
+            }
+        };
+
+        for endpoint in &contract.endpoints {
+            if endpoint.mutating && self.skip_mutating_endpoint(endpoint.name(), &mut result) {
+                continue;
+            }
+            let endpoint = endpoint.clone();
+            let address = address.clone();
+            let endpoint_name = endpoint.name().to_string();
+            self.probe_peach_endpoint(move || endpoint.run(&address), &endpoint_name, &mut result);
+        }
+
+        // return result
+        result
+    }
+}
+
+/// helper function which locks the websocket connection to peach-buttons shared between calls
+fn lock_client(
+    client: &Mutex<ButtonsClient>,
+) -> Result<std::sync::MutexGuard<'_, ButtonsClient>, ProbeError> {
+    client
+        .lock()
+        .map_err(|_| ProbeError::UnexpectedResponse("connection poisoned".to_string()))
 }
@@ -0,0 +1,79 @@
+//! Helpers for inspecting the running process of a systemd unit through /proc,
+//! so that a service can be probed from its own point of view.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::error::ProbeError;
+
+/// get the environment variables which a process was started with
+pub fn environment(pid: u32) -> Result<HashMap<String, String>, ProbeError> {
+    let environ = fs::read(format!("/proc/{}/environ", pid))?;
+    let vars = environ
+        .split(|byte| *byte == 0)
+        .filter_map(|var| {
+            let var = String::from_utf8_lossy(var);
+            let mut parts = var.splitn(2, '=');
+            let key = parts.next()?.to_string();
+            let value = parts.next()?.to_string();
+            Some((key, value))
+        })
+        .collect();
+    Ok(vars)
+}
+
+/// check that the open file descriptors of a process can be read,
+/// which for a process of another user is only possible as root
+pub fn can_inspect(pid: u32) -> Result<(), ProbeError> {
+    fs::read_dir(format!("/proc/{}/fd", pid))?;
+    Ok(())
+}
+
+/// check whether an error was caused by lacking the permission to read /proc of another process
+pub fn is_permission_denied(err: &ProbeError) -> bool {
+    matches!(err, ProbeError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// check whether a process holds an established tcp connection to the given remote port
+pub fn is_connected_to_port(pid: u32, port: u16) -> Result<bool, ProbeError> {
+    // collect the inodes of all sockets which the process has open
+    let mut inodes = Vec::new();
+    for entry in fs::read_dir(format!("/proc/{}/fd", pid))? {
+        if let Ok(target) = fs::read_link(entry?.path()) {
+            let target = target.to_string_lossy();
+            if let Some(inode) = target
+                .strip_prefix("socket:[")
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                inodes.push(inode.to_string());
+            }
+        }
+    }
+
+    // each line of /proc/net/tcp has the form:
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    for table in &["/proc/net/tcp", "/proc/net/tcp6"] {
+        let contents = match fs::read_to_string(table) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                continue;
+            }
+            let remote_port = fields[2]
+                .rsplit(':')
+                .next()
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            // state 01 is TCP_ESTABLISHED
+            let is_established = fields[3] == "01";
+            if is_established && remote_port == Some(port) && inodes.iter().any(|i| i == fields[9])
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
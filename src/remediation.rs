@@ -0,0 +1,581 @@
+//! Suggestions of possible fixes for known failure patterns.
+//!
+//! Each rule looks for a pattern in the result of probing a service, such as a service which is offline
+//! with a particular line in its log, or an endpoint which returned a particular JSON-RPC error code,
+//! and returns a human-readable diagnosis along with commands which may fix the problem.
+//! In commands, `{unit}` is replaced by the name of the systemd unit and `{package}` by the name of the package.
+
+use serde_derive::Serialize;
+
+use crate::dpkg::VersionError;
+use crate::error::ErrorCategory;
+use crate::probe::{EndpointOutcome, EndpointResult, ProbeResult};
+
+/// Suggestion is a diagnosis of a problem found while probing a service, and commands which may fix it
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    // name of the rule which matched
+    pub rule: String,
+    // name of the endpoint the problem was found with, if it is specific to one endpoint
+    pub endpoint: Option<String>,
+    // human-readable description of the likely cause of the problem
+    pub diagnosis: String,
+    // commands which may fix the problem, or help to investigate it
+    pub commands: Vec<String>,
+}
+
+/// Rule returns suggestions for any problems it recognises in the result of probing a service
+type Rule = fn(&ProbeResult) -> Vec<Suggestion>;
+
+/// all rules, in the order their suggestions are shown
+const RULES: [Rule; 12] = [
+    unit_not_found,
+    offline_with_log_pattern,
+    offline,
+    crash_looping,
+    connection_refused,
+    json_rpc_error_code,
+    timed_out,
+    unknown_version,
+    restart_required,
+    incompatible_version,
+    state_not_restored,
+    invalid_response,
+];
+
+/// patterns in the log of an offline service, as (pattern, diagnosis, commands)
+const LOG_PATTERNS: [(&str, &str, &[&str]); 5] = [
+    (
+        "Address already in use",
+        "another process is already listening on the port used by the service",
+        &["sudo ss -ltnp", "sudo systemctl restart {unit}"],
+    ),
+    (
+        "Permission denied",
+        "the service does not have permission to access a file or device it needs",
+        &["sudo journalctl -u {unit} -n 50", "groups {package}"],
+    ),
+    (
+        "No such file or directory",
+        "a file needed by the service is missing, which can happen after an interrupted install",
+        &["sudo apt-get install --reinstall {package}"],
+    ),
+    (
+        "start request repeated too quickly",
+        "systemd stopped restarting the service because it kept crashing",
+        &[
+            "sudo journalctl -u {unit} -n 100",
+            "sudo systemctl reset-failed {unit}",
+            "sudo systemctl restart {unit}",
+        ],
+    ),
+    (
+        "panicked at",
+        "the service crashed with a panic",
+        &[
+            "sudo journalctl -u {unit} -n 100",
+            "sudo systemctl restart {unit}",
+        ],
+    ),
+];
+
+/// known JSON-RPC error codes, as (code, diagnosis, commands)
+const JSON_RPC_CODES: [(i64, &str, &[&str]); 5] = [
+    (
+        -32601,
+        "the method does not exist, so the installed version of the service may be older than expected",
+        &["apt-cache policy {package}", "sudo apt-get install --only-upgrade {package}"],
+    ),
+    (
+        -32602,
+        "the params were rejected, so the api of the installed version of the service may have changed",
+        &["apt-cache policy {package}"],
+    ),
+    (
+        -32700,
+        "the service could not parse the request",
+        &["sudo journalctl -u {unit} -n 50"],
+    ),
+    (
+        -32027,
+        "no saved network was found with the given ssid, so wpa_supplicant may be out of sync with its config",
+        &["wpa_cli -i wlan0 list_networks", "wpa_cli -i wlan0 reconfigure"],
+    ),
+    (
+        -32000,
+        "the service returned a server error",
+        &["sudo journalctl -u {unit} -n 50"],
+    ),
+];
+
+/// run all rules against the result of probing a service, returning their suggestions
+pub fn suggest(result: &ProbeResult) -> Vec<Suggestion> {
+    RULES.iter().flat_map(|rule| rule(result)).collect()
+}
+
+/// helper function which builds a suggestion, filling in the unit and package names in its commands
+fn suggestion(
+    result: &ProbeResult,
+    rule: &str,
+    endpoint: Option<&EndpointResult>,
+    diagnosis: &str,
+    commands: &[&str],
+) -> Suggestion {
+    Suggestion {
+        rule: rule.to_string(),
+        endpoint: endpoint.map(|endpoint| endpoint.endpoint.clone()),
+        diagnosis: diagnosis.to_string(),
+        commands: commands
+            .iter()
+            .map(|command| {
+                command
+                    .replace("{unit}", &result.unit)
+                    .replace("{package}", &result.microservice)
+            })
+            .collect(),
+    }
+}
+
+/// helper function which returns true if the service has no systemd unit
+fn is_not_found(result: &ProbeResult) -> bool {
+    matches!(&result.unit_status, Some(unit_status) if unit_status.is_not_found())
+}
+
+/// the service is offline because its systemd unit does not exist
+fn unit_not_found(result: &ProbeResult) -> Vec<Suggestion> {
+    if !is_not_found(result) {
+        return Vec::new();
+    }
+    vec![suggestion(
+        result,
+        "unit_not_found",
+        None,
+        "the systemd unit of the service was not found, so the package may not be installed, \
+         or the unit may have a different name",
+        &[
+            "apt-cache policy {package}",
+            "systemctl list-unit-files | grep {package}",
+        ],
+    )]
+}
+
+/// the service is offline, and its log contains a known error
+fn offline_with_log_pattern(result: &ProbeResult) -> Vec<Suggestion> {
+    let log = match (&result.service_log, result.is_running) {
+        (Some(log), false) => log,
+        _ => return Vec::new(),
+    };
+    LOG_PATTERNS
+        .iter()
+        .filter(|(pattern, _, _)| log.contains(pattern))
+        .map(|(_, diagnosis, commands)| {
+            suggestion(
+                result,
+                "offline_with_log_pattern",
+                None,
+                diagnosis,
+                commands,
+            )
+        })
+        .collect()
+}
+
+/// the service is offline, and its log contains no known errors
+fn offline(result: &ProbeResult) -> Vec<Suggestion> {
+    let known_pattern = matches!(
+        &result.service_log,
+        Some(log) if LOG_PATTERNS.iter().any(|(pattern, _, _)| log.contains(pattern))
+    );
+    if result.is_running || known_pattern || is_not_found(result) || !result.probe_errors.is_empty()
+    {
+        return Vec::new();
+    }
+    vec![suggestion(
+        result,
+        "offline",
+        None,
+        "the service is not running",
+        &[
+            "sudo systemctl status {unit}",
+            "sudo journalctl -u {unit} -n 50",
+            "sudo systemctl restart {unit}",
+        ],
+    )]
+}
+
+/// the service is running, but systemd keeps restarting it
+fn crash_looping(result: &ProbeResult) -> Vec<Suggestion> {
+    if !result.is_running || !result.crash_looping {
+        return Vec::new();
+    }
+    vec![suggestion(
+        result,
+        "crash_looping",
+        None,
+        "systemd keeps restarting the service, so it is crashing soon after it starts",
+        &[
+            "sudo journalctl -u {unit} -n 100",
+            "systemctl show {unit} --property=NRestarts,ExecMainStartTimestamp",
+        ],
+    )]
+}
+
+/// the service is running, but refused connections to an endpoint
+fn connection_refused(result: &ProbeResult) -> Vec<Suggestion> {
+    if !result.is_running {
+        return Vec::new();
+    }
+    // only one suggestion is needed, however many endpoints refused connections
+    result
+        .failing_endpoints()
+        .find(|endpoint| matches!(&endpoint.error, Some(error) if error.connection_refused))
+        .map(|endpoint| {
+            suggestion(
+                result,
+                "connection_refused",
+                Some(endpoint),
+                "the service is running but refused the connection, so it may be listening on a \
+                 different address than the one peach-probe was configured with",
+                &[
+                    "sudo ss -ltnp",
+                    "systemctl show {unit} --property=Environment",
+                ],
+            )
+        })
+        .into_iter()
+        .collect()
+}
+
+/// an endpoint returned a known JSON-RPC error code
+fn json_rpc_error_code(result: &ProbeResult) -> Vec<Suggestion> {
+    result
+        .failing_endpoints()
+        .filter_map(|endpoint| {
+            let code = endpoint.error.as_ref()?.code?;
+            let (_, diagnosis, commands) = JSON_RPC_CODES
+                .iter()
+                .find(|(known_code, _, _)| *known_code == code)?;
+            Some(suggestion(
+                result,
+                "json_rpc_error_code",
+                Some(endpoint),
+                diagnosis,
+                commands,
+            ))
+        })
+        .collect()
+}
+
+/// an endpoint did not return before its timeout, so the service may be hung
+fn timed_out(result: &ProbeResult) -> Vec<Suggestion> {
+    result
+        .failing_endpoints()
+        .find(|endpoint| endpoint.outcome == EndpointOutcome::Timeout)
+        .map(|endpoint| {
+            suggestion(
+                result,
+                "timed_out",
+                Some(endpoint),
+                "the service did not respond in time, so it may be hung or overloaded",
+                &["top -b -n 1", "sudo systemctl restart {unit}"],
+            )
+        })
+        .into_iter()
+        .collect()
+}
+
+/// the installed version of the package could not be determined
+fn unknown_version(result: &ProbeResult) -> Vec<Suggestion> {
+    let (diagnosis, commands): (&str, &[&str]) = match result.version_error {
+        // a missing unit is already reported by unit_not_found
+        Some(VersionError::NotInstalled) if is_not_found(result) => return Vec::new(),
+        Some(VersionError::NotInstalled) => (
+            "the package is not installed according to dpkg",
+            &["apt-cache policy {package}", "sudo apt-get install {package}"],
+        ),
+        Some(VersionError::Broken) => (
+            "the package is listed as installed, but its version is invalid or its install did not complete",
+            &["sudo dpkg --configure -a", "sudo apt-get install --reinstall {package}"],
+        ),
+        Some(VersionError::StatusUnreadable) => (
+            "the dpkg status file could not be read, so the --dpkg-status path may be wrong",
+            &["ls -l /var/lib/dpkg/status"],
+        ),
+        None => return Vec::new(),
+    };
+    vec![suggestion(
+        result,
+        "unknown_version",
+        None,
+        diagnosis,
+        commands,
+    )]
+}
+
+/// the running service is not running the installed version
+fn restart_required(result: &ProbeResult) -> Vec<Suggestion> {
+    match &result.version_mismatch {
+        Some(mismatch) => vec![suggestion(
+            result,
+            "restart_required",
+            None,
+            &format!("{}, so the service needs to be restarted", mismatch),
+            &["sudo systemctl restart {unit}"],
+        )],
+        None => Vec::new(),
+    }
+}
+
+/// the installed version is outdated, or incompatible with the versions of other services
+fn incompatible_version(result: &ProbeResult) -> Vec<Suggestion> {
+    if result.incompatibilities.is_empty() {
+        return Vec::new();
+    }
+    vec![suggestion(
+        result,
+        "incompatible_version",
+        None,
+        "the installed version is not known to work with the other services, so the package may need to be \
+         upgraded or pinned to a known working version",
+        &["apt-cache policy {package}"],
+    )]
+}
+
+/// the state changed by mutating endpoints was not fully restored
+fn state_not_restored(result: &ProbeResult) -> Vec<Suggestion> {
+    if result.state_restored != Some(false) {
+        return Vec::new();
+    }
+    vec![suggestion(
+        result,
+        "state_not_restored",
+        None,
+        "the state of the device changed by peach-probe was not fully restored",
+        &[
+            "wpa_cli -i wlan0 list_networks",
+            "wpa_cli -i wlan0 reconfigure",
+        ],
+    )]
+}
+
+/// an endpoint returned a response which failed validation
+fn invalid_response(result: &ProbeResult) -> Vec<Suggestion> {
+    result
+        .failing_endpoints()
+        .find(|endpoint| {
+            matches!(&endpoint.error, Some(error) if error.category == ErrorCategory::InvalidResponse)
+        })
+        .map(|endpoint| {
+            suggestion(
+                result,
+                "invalid_response",
+                Some(endpoint),
+                "the service returned data which does not make sense, so the system it reads from may be misconfigured, \
+                 or the api of the installed version may have changed",
+                &["apt-cache policy {package}", "sudo journalctl -u {unit} -n 50"],
+            )
+        })
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ProbeError;
+    use crate::probe::EndpointError;
+    use crate::systemd::UnitStatus;
+    use std::io;
+
+    /// helper function which builds the result of a running service
+    fn running(microservice: &str) -> ProbeResult {
+        let mut result = ProbeResult::new(microservice);
+        result.is_running = true;
+        result
+    }
+
+    /// helper function which builds the result of an offline service, with the given log
+    fn offline_with_log(microservice: &str, log: &str) -> ProbeResult {
+        let mut result = ProbeResult::new(microservice);
+        result.service_log = Some(log.to_string());
+        result.unit_status = Some(unit_status("loaded", "failed"));
+        result
+    }
+
+    /// helper function which builds the status of a unit
+    fn unit_status(load_state: &str, active_state: &str) -> UnitStatus {
+        UnitStatus {
+            load_state: load_state.to_string(),
+            active_state: active_state.to_string(),
+            sub_state: "dead".to_string(),
+            main_pid: None,
+            restarts: None,
+            main_start_timestamp: None,
+        }
+    }
+
+    /// helper function which builds the result of an endpoint which failed with the given error
+    fn failing(endpoint: &str, outcome: EndpointOutcome, error: ProbeError) -> EndpointResult {
+        EndpointResult {
+            endpoint: endpoint.to_string(),
+            outcome,
+            error: Some(EndpointError::from(&error)),
+            latency_ms: Some(1.0),
+            slow: false,
+            skip_reason: None,
+        }
+    }
+
+    /// helper function which gets the names of the rules which matched
+    fn rules(result: &ProbeResult) -> Vec<String> {
+        suggest(result)
+            .into_iter()
+            .map(|suggestion| suggestion.rule)
+            .collect()
+    }
+
+    #[test]
+    fn healthy_service_has_no_suggestions() {
+        assert!(suggest(&running("peach-stats")).is_empty());
+    }
+
+    #[test]
+    fn suggests_fixes_for_offline_services() {
+        let result = offline_with_log("peach-web", "Error: Address already in use (os error 98)");
+        let suggestions = suggest(&result);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].rule, "offline_with_log_pattern");
+        assert_eq!(
+            suggestions[0].commands,
+            ["sudo ss -ltnp", "sudo systemctl restart peach-web"]
+        );
+
+        // without a known pattern, the generic offline rule matches instead
+        let result = offline_with_log("peach-web", "exited with status 1");
+        assert_eq!(rules(&result), ["offline"]);
+
+        // errors of peach-probe itself mean the service may not really be offline
+        let mut result = offline_with_log("peach-web", "exited with status 1");
+        result
+            .probe_errors
+            .push("error retrieving service status".to_string());
+        assert!(rules(&result).is_empty());
+    }
+
+    #[test]
+    fn missing_unit_is_only_reported_once() {
+        let mut result = ProbeResult::new("peach-web");
+        result.unit_status = Some(unit_status("not-found", "inactive"));
+        result.version_error = Some(VersionError::NotInstalled);
+        assert_eq!(rules(&result), ["unit_not_found"]);
+    }
+
+    #[test]
+    fn suggests_fixes_for_crash_looping_services() {
+        let mut result = running("peach-web");
+        result.crash_looping = true;
+        assert_eq!(rules(&result), ["crash_looping"]);
+    }
+
+    #[test]
+    fn suggests_checking_address_when_connections_are_refused() {
+        let refused = || {
+            ProbeError::HttpError(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "connection refused",
+            ))
+        };
+        let mut result = running("peach-web");
+        result
+            .endpoints
+            .push(failing("home", EndpointOutcome::Failure, refused()));
+        result
+            .endpoints
+            .push(failing("login", EndpointOutcome::Failure, refused()));
+        let suggestions = suggest(&result);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].rule, "connection_refused");
+        assert_eq!(suggestions[0].endpoint.as_deref(), Some("home"));
+
+        // other io errors, or errors whose text merely mentions a refused connection, do not match
+        let mut result = running("peach-web");
+        result.endpoints.push(failing(
+            "home",
+            EndpointOutcome::Failure,
+            ProbeError::UnexpectedResponse("Connection refused".to_string()),
+        ));
+        assert!(rules(&result).is_empty());
+    }
+
+    #[test]
+    fn suggests_fixes_for_known_json_rpc_error_codes() {
+        let mut result = running("peach-network");
+        result.endpoints.push(failing(
+            "connect",
+            EndpointOutcome::Failure,
+            ProbeError::JsonRpcError {
+                code: -32027,
+                message: "Failed to find id for given ssid".to_string(),
+            },
+        ));
+        result.endpoints.push(failing(
+            "traffic",
+            EndpointOutcome::Failure,
+            ProbeError::JsonRpcError {
+                code: -1,
+                message: "unknown".to_string(),
+            },
+        ));
+        let suggestions = suggest(&result);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].rule, "json_rpc_error_code");
+        assert_eq!(suggestions[0].endpoint.as_deref(), Some("connect"));
+    }
+
+    #[test]
+    fn suggests_fixes_for_timeouts_and_invalid_responses() {
+        let mut result = running("peach-stats");
+        result.endpoints.push(failing(
+            "uptime",
+            EndpointOutcome::InvalidResponse,
+            ProbeError::InvalidResponse("uptime should be a non-negative number".to_string()),
+        ));
+        result.endpoints.push(failing(
+            "mem_stats",
+            EndpointOutcome::Timeout,
+            ProbeError::Timeout { timeout_ms: 30000 },
+        ));
+        assert_eq!(rules(&result), ["timed_out", "invalid_response"]);
+    }
+
+    #[test]
+    fn suggests_fixes_for_version_problems() {
+        let mut result = running("peach-stats");
+        result.version_error = Some(VersionError::Broken);
+        result.version_mismatch =
+            Some("running version 0.1.0 differs from installed version 0.2.0".to_string());
+        result.incompatibilities.push("outdated".to_string());
+        let suggestions = suggest(&result);
+        assert_eq!(
+            rules(&result),
+            [
+                "unknown_version",
+                "restart_required",
+                "incompatible_version"
+            ]
+        );
+        assert_eq!(
+            suggestions[1].diagnosis,
+            "running version 0.1.0 differs from installed version 0.2.0, so the service needs to be restarted"
+        );
+    }
+
+    #[test]
+    fn suggests_fixes_when_state_was_not_restored() {
+        let mut result = running("peach-network");
+        result.state_restored = Some(true);
+        assert!(rules(&result).is_empty());
+        result.state_restored = Some(false);
+        assert_eq!(rules(&result), ["state_not_restored"]);
+    }
+}
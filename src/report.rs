@@ -0,0 +1,474 @@
+use clap::arg_enum;
+use serde_derive::Serialize;
+
+use crate::error::ProbeError;
+use crate::probe::{EndpointOutcome, ProbeResult};
+
+arg_enum! {
+    #[derive(Debug)]
+    pub enum ReportFormat {
+        Text,
+        Json,
+        Junit
+    }
+}
+
+/// JsonReport is the document printed to stdout when using --format json
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    // version of peach-probe which generated the report
+    probe_version: &'a str,
+    // results of probing each microservice, in the order they were probed
+    services: &'a [ProbeResult],
+}
+
+/// prints the final report of all probe results in the given format
+pub fn print_report(
+    results: &[ProbeResult],
+    format: &ReportFormat,
+    verbose: bool,
+) -> Result<(), ProbeError> {
+    match format {
+        ReportFormat::Text => {
+            print_text_report(results, verbose);
+            Ok(())
+        }
+        ReportFormat::Json => print_json_report(results),
+        ReportFormat::Junit => {
+            print!("{}", junit_report(results));
+            Ok(())
+        }
+    }
+}
+
+/// prints all probe results as a single json document on stdout
+fn print_json_report(results: &[ProbeResult]) -> Result<(), ProbeError> {
+    let report = JsonReport {
+        probe_version: env!("CARGO_PKG_VERSION"),
+        services: results,
+    };
+    let json = serde_json::to_string_pretty(&report)?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// renders all probe results as a JUnit XML document, for CI systems
+/// each microservice is a testsuite, with a testcase for its status and one for each endpoint
+fn junit_report(results: &[ProbeResult]) -> String {
+    let mut suites = Vec::new();
+    let (mut total_tests, mut total_failures, mut total_errors) = (0, 0, 0);
+    for result in results {
+        let mut cases = Vec::new();
+        let mut failures = 0;
+
+        // whether the service is running is reported as its own testcase
+        if result.is_running && !result.crash_looping {
+            cases.push(junit_testcase(result, "status", 0.0, ""));
+        } else if result.is_running {
+            failures += 1;
+            cases.push(junit_testcase(
+                result,
+                "status",
+                0.0,
+                &format!(
+                    "<failure type=\"crash_loop\" message=\"{}\"/>",
+                    xml_escape(&crash_loop_message(result))
+                ),
+            ));
+        } else {
+            failures += 1;
+            let log = result.service_log.as_deref().unwrap_or("log not found");
+            cases.push(junit_testcase(
+                result,
+                "status",
+                0.0,
+                &format!(
+                    "<failure type=\"offline\" message=\"service is not running{}\">{}</failure>",
+                    xml_escape(&unit_state(result)),
+                    xml_escape(log)
+                ),
+            ));
+        }
+
+        // whether the installed version is the one expected is reported as its own testcase
+        if result.version != "Unchecked" {
+            let problems: Vec<&str> = result
+                .version_mismatch
+                .iter()
+                .chain(&result.incompatibilities)
+                .map(String::as_str)
+                .collect();
+            let outcome = if problems.is_empty() {
+                String::new()
+            } else {
+                failures += 1;
+                format!(
+                    "<failure type=\"version\" message=\"{}\">{}</failure>",
+                    xml_escape(problems[0]),
+                    xml_escape(&problems.join("\n"))
+                )
+            };
+            cases.push(junit_testcase(result, "version", 0.0, &outcome));
+        }
+
+        for endpoint in &result.endpoints {
+            let latency_ms = endpoint.latency_ms.unwrap_or_default();
+            let outcome = match (&endpoint.outcome, &endpoint.error) {
+                (EndpointOutcome::Success, _) => String::new(),
+                (EndpointOutcome::Skipped, _) => format!(
+                    "<skipped message=\"{}\"/>",
+                    xml_escape(endpoint.skip_reason.as_deref().unwrap_or_default())
+                ),
+                (outcome, error) => {
+                    failures += 1;
+                    let (message, raw) = match error {
+                        Some(error) => (error.message.as_str(), error.raw.as_str()),
+                        None => ("", ""),
+                    };
+                    format!(
+                        "<failure type=\"{}\" message=\"{}\">{}</failure>",
+                        outcome_name(*outcome),
+                        xml_escape(message),
+                        xml_escape(raw)
+                    )
+                }
+            };
+            cases.push(junit_testcase(
+                result,
+                &endpoint.endpoint,
+                latency_ms,
+                &outcome,
+            ));
+        }
+
+        // errors of peach-probe itself are reported as errors rather than failures
+        let errors = result.probe_errors.len();
+        for error in &result.probe_errors {
+            cases.push(junit_testcase(
+                result,
+                "peach-probe",
+                0.0,
+                &format!(
+                    "<error message=\"{}\">{}</error>",
+                    xml_escape(error),
+                    xml_escape(error)
+                ),
+            ));
+        }
+
+        let time = result.endpoints.iter().fold(0.0, |total, endpoint| {
+            total + endpoint.latency_ms.unwrap_or_default()
+        }) / 1000.0;
+        total_tests += cases.len();
+        total_failures += failures;
+        total_errors += errors;
+        suites.push(format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n    <properties>\n      <property name=\"version\" value=\"{}\"/>\n      <property name=\"required\" value=\"{}\"/>\n    </properties>\n{}  </testsuite>\n",
+            xml_escape(&result.microservice),
+            cases.len(),
+            failures,
+            errors,
+            result.endpoint_names(EndpointOutcome::Skipped).len(),
+            time,
+            xml_escape(&result.version),
+            result.required,
+            cases.concat()
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"peach-probe\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n{}</testsuites>\n",
+        total_tests,
+        total_failures,
+        total_errors,
+        suites.concat()
+    )
+}
+
+/// helper function which formats a single JUnit testcase, with the given failure, error or skipped element
+fn junit_testcase(result: &ProbeResult, name: &str, latency_ms: f64, outcome: &str) -> String {
+    if outcome.is_empty() {
+        format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"/>\n",
+            xml_escape(&result.microservice),
+            xml_escape(name),
+            latency_ms / 1000.0
+        )
+    } else {
+        format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">\n      {}\n    </testcase>\n",
+            xml_escape(&result.microservice),
+            xml_escape(name),
+            latency_ms / 1000.0,
+            outcome
+        )
+    }
+}
+
+/// helper function which escapes text for use in XML attributes and elements
+/// control characters which XML 1.0 does not allow, even escaped, are replaced with U+FFFD
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// helper function which gets the name of an endpoint outcome, as used in reports
+fn outcome_name(outcome: EndpointOutcome) -> &'static str {
+    match outcome {
+        EndpointOutcome::Success => "success",
+        EndpointOutcome::Failure => "failure",
+        EndpointOutcome::InvalidResponse => "invalid_response",
+        EndpointOutcome::Timeout => "timeout",
+        EndpointOutcome::Skipped => "skipped",
+    }
+}
+
+/// prints a human-readable summary of how many microservices returned successes and failures
+/// in verbose mode, the latency of every endpoint is also printed
+fn print_text_report(results: &[ProbeResult], verbose: bool) {
+    println!("[ generating report ]");
+    for result in results {
+        let failures = result.endpoint_names(EndpointOutcome::Failure);
+        let invalid_responses = result.endpoint_names(EndpointOutcome::InvalidResponse);
+        let timeouts = result.endpoint_names(EndpointOutcome::Timeout);
+        let skipped = result.endpoint_names(EndpointOutcome::Skipped);
+        let num_failures = failures.len() + invalid_responses.len() + timeouts.len();
+        // label optional services, since their problems do not affect the exit code
+        let microservice = if result.required {
+            result.microservice.clone()
+        } else {
+            format!("{} (optional)", result.microservice)
+        };
+        let report;
+        // if service is running according to systemctl show
+        if result.is_running {
+            if num_failures == 0 {
+                report = format!(
+                    "- {} [version: {}] is online.",
+                    microservice, result.version
+                );
+                println!("{}", report);
+            }
+            // even if its running, some endpoints could still return errors
+            else {
+                report = format!(
+                    "- {} [version: {}] is online but {} endpoints returned errors: {:?}, {} returned invalid responses: {:?} and {} timed out: {:?}",
+                    microservice,
+                    result.version,
+                    failures.len(),
+                    failures,
+                    invalid_responses.len(),
+                    invalid_responses,
+                    timeouts.len(),
+                    timeouts
+                );
+                eprintln!("{}", report);
+                // show why each endpoint failed
+                for endpoint in result.failing_endpoints() {
+                    if let Some(error) = &endpoint.error {
+                        let code = error
+                            .code
+                            .map(|code| format!(" {}", code))
+                            .unwrap_or_default();
+                        eprintln!(
+                            "  - {} ({}{}): {}",
+                            endpoint.endpoint,
+                            outcome_name(endpoint.outcome),
+                            code,
+                            error.message
+                        );
+                    }
+                }
+            }
+        }
+        // if service is not running according to systemctl show, print the service log
+        else {
+            match &result.service_log {
+                Some(service_log) => {
+                    report = format!(
+                        "- {} [version: {}] is offline{}, with log:\n {}",
+                        microservice,
+                        result.version,
+                        unit_state(result),
+                        service_log
+                    );
+                }
+                None => {
+                    report = format!(
+                        "- {} [version: {}] is offline{}, log not found",
+                        microservice,
+                        result.version,
+                        unit_state(result)
+                    );
+                }
+            };
+            eprintln!("{}", report);
+        }
+
+        // services which systemd keeps restarting, which may look online when they are probed
+        if result.is_running && result.crash_looping {
+            eprintln!("  - {}", crash_loop_message(result));
+        }
+
+        // services which are not running the installed version
+        if let Some(mismatch) = &result.version_mismatch {
+            eprintln!("  - {}", mismatch);
+        }
+        // versions which are outdated or incompatible with other services
+        for incompatibility in &result.incompatibilities {
+            eprintln!("  - {}", incompatibility);
+        }
+
+        // mutating endpoints which were not called, in read-only mode or because the state could not be captured
+        if !skipped.is_empty() {
+            println!(
+                "  - {} mutating endpoints were skipped: {:?}",
+                skipped.len(),
+                skipped
+            );
+        }
+
+        // whether the state changed by mutating endpoints was restored afterwards
+        match result.state_restored {
+            Some(true) => println!("  - state was restored after probing"),
+            Some(false) => eprintln!("  - state was NOT fully restored after probing"),
+            None => (),
+        }
+
+        // endpoints which succeeded but exceeded their latency warning threshold
+        for endpoint in result.endpoints.iter().filter(|endpoint| endpoint.slow) {
+            eprintln!(
+                "  - {} endpoint is slow ({:.0}ms)",
+                endpoint.endpoint,
+                endpoint.latency_ms.unwrap_or_default()
+            );
+        }
+        // suggested fixes for known problems
+        for suggestion in &result.suggestions {
+            match &suggestion.endpoint {
+                Some(endpoint) => eprintln!("  ? {}: {}", endpoint, suggestion.diagnosis),
+                None => eprintln!("  ? {}", suggestion.diagnosis),
+            }
+            for command in &suggestion.commands {
+                eprintln!("      $ {}", command);
+            }
+        }
+
+        if verbose {
+            for endpoint in &result.endpoints {
+                if let Some(latency_ms) = endpoint.latency_ms {
+                    println!("  - {} took {:.1}ms", endpoint.endpoint, latency_ms);
+                }
+            }
+        }
+    }
+}
+
+/// helper function which describes the state of the systemd unit of an offline service, e.g. " (unit not found)"
+fn unit_state(result: &ProbeResult) -> String {
+    match &result.unit_status {
+        Some(unit_status) => format!(" ({})", unit_status.describe()),
+        None => String::new(),
+    }
+}
+
+/// helper function which describes how often a crash-looping service was restarted
+fn crash_loop_message(result: &ProbeResult) -> String {
+    format!(
+        "service is crash-looping, having been restarted {} times recently",
+        result.recent_restarts.unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{EndpointError, EndpointResult};
+
+    /// helper function which builds the result of an endpoint
+    fn endpoint(name: &str, outcome: EndpointOutcome, error: Option<ProbeError>) -> EndpointResult {
+        EndpointResult {
+            endpoint: name.to_string(),
+            outcome,
+            error: error.as_ref().map(EndpointError::from),
+            latency_ms: Some(12.0),
+            slow: false,
+            skip_reason: None,
+        }
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">'b' & c</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;b&apos; &amp; c&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn replaces_invalid_control_characters() {
+        assert_eq!(
+            xml_escape("a\u{0}b\u{8}c\u{b}d\u{c}e\u{e}f\u{1f}g"),
+            "a\u{FFFD}b\u{FFFD}c\u{FFFD}d\u{FFFD}e\u{FFFD}f\u{FFFD}g"
+        );
+        // tabs and line breaks are allowed in XML, so are kept
+        assert_eq!(xml_escape("a\tb\nc\rd"), "a\tb\nc\rd");
+    }
+
+    #[test]
+    fn renders_junit_report() {
+        let mut result = ProbeResult::new("peach-stats");
+        result.version = "0.1.0".to_string();
+        result.is_running = true;
+        result
+            .endpoints
+            .push(endpoint("ping", EndpointOutcome::Success, None));
+        result.endpoints.push(endpoint(
+            "uptime",
+            EndpointOutcome::InvalidResponse,
+            Some(ProbeError::InvalidResponse(
+                "uptime is \u{1b}[31m-1".to_string(),
+            )),
+        ));
+        let mut skipped = endpoint("power", EndpointOutcome::Skipped, None);
+        skipped.latency_ms = None;
+        skipped.skip_reason = Some("mutating endpoint skipped in read-only mode".to_string());
+        result.endpoints.push(skipped);
+        result.probe_errors.push("error getting log".to_string());
+
+        let report = junit_report(&[result]);
+        assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(report
+            .contains("<testsuites name=\"peach-probe\" tests=\"6\" failures=\"1\" errors=\"1\">"));
+        assert!(report.contains(
+            "<testsuite name=\"peach-stats\" tests=\"6\" failures=\"1\" errors=\"1\" skipped=\"1\" time=\"0.024\">"
+        ));
+        assert!(
+            report.contains("<testcase classname=\"peach-stats\" name=\"status\" time=\"0.000\"/>")
+        );
+        assert!(
+            report.contains("<testcase classname=\"peach-stats\" name=\"ping\" time=\"0.012\"/>")
+        );
+        assert!(report.contains(
+            "<failure type=\"invalid_response\" message=\"Invalid response: uptime is \u{FFFD}[31m-1\">"
+        ));
+        assert!(
+            report.contains("<skipped message=\"mutating endpoint skipped in read-only mode\"/>")
+        );
+        assert!(report.contains("<error message=\"error getting log\">error getting log</error>"));
+        assert!(report.ends_with("</testsuites>\n"));
+        assert!(!report.contains('\u{1b}'));
+    }
+}
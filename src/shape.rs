@@ -0,0 +1,152 @@
+//! Checking json values against an expected shape.
+//!
+//! A shape is itself a json value, where:
+//! - a string names the expected type: "string", "number", "integer", "boolean", "array",
+//!   "object", "null" or "any", optionally followed by "?" to also allow null
+//! - any other string must match the value exactly
+//! - an object lists fields which must be present, each with their own shape
+//! - an array with a single element gives the shape of every element of an array
+
+use serde_json::Value;
+
+/// check that value matches shape, returning a description of the first mismatch found
+pub fn check(value: &Value, shape: &Value) -> Result<(), String> {
+    check_at(value, shape, "result")
+}
+
+/// helper function which checks a value, using path to describe where a mismatch was found
+fn check_at(value: &Value, shape: &Value, path: &str) -> Result<(), String> {
+    match shape {
+        Value::String(type_name) => {
+            let (type_name, nullable) = match type_name.strip_suffix('?') {
+                Some(type_name) => (type_name, true),
+                None => (type_name.as_str(), false),
+            };
+            let matches = match type_name {
+                "any" => true,
+                "string" => value.is_string(),
+                "number" => value.is_number(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "boolean" => value.is_boolean(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                "null" => value.is_null(),
+                // strings which are not type names are literal values
+                _ => value == shape,
+            };
+            if matches || (nullable && value.is_null()) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "expected {} at {}, found {}",
+                    type_name, path, value
+                ))
+            }
+        }
+        Value::Object(fields) => {
+            let object = value
+                .as_object()
+                .ok_or_else(|| format!("expected object at {}, found {}", path, value))?;
+            for (field, field_shape) in fields {
+                let field_path = format!("{}.{}", path, field);
+                match object.get(field) {
+                    Some(field_value) => check_at(field_value, field_shape, &field_path)?,
+                    None => return Err(format!("missing field {}", field_path)),
+                }
+            }
+            Ok(())
+        }
+        Value::Array(element_shapes) => {
+            let elements = value
+                .as_array()
+                .ok_or_else(|| format!("expected array at {}, found {}", path, value))?;
+            if let Some(element_shape) = element_shapes.first() {
+                for (index, element) in elements.iter().enumerate() {
+                    check_at(element, element_shape, &format!("{}[{}]", path, index))?;
+                }
+            }
+            Ok(())
+        }
+        // any other shape must match the value exactly
+        _ => {
+            if value == shape {
+                Ok(())
+            } else {
+                Err(format!("expected {} at {}, found {}", shape, path, value))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn checks_types() {
+        assert!(check(&json!("up"), &json!("string")).is_ok());
+        assert!(check(&json!(4.2), &json!("number")).is_ok());
+        assert!(check(&json!(4), &json!("integer")).is_ok());
+        assert!(check(&json!(true), &json!("boolean")).is_ok());
+        assert!(check(&json!(null), &json!("null")).is_ok());
+        assert!(check(&json!({ "a": 1 }), &json!("any")).is_ok());
+        assert_eq!(
+            check(&json!(4.2), &json!("integer")),
+            Err("expected integer at result, found 4.2".to_string())
+        );
+        assert!(check(&json!("4"), &json!("number")).is_err());
+    }
+
+    #[test]
+    fn nullable_types_allow_null() {
+        assert!(check(&json!(null), &json!("string?")).is_ok());
+        assert!(check(&json!("wlan0"), &json!("string?")).is_ok());
+        assert!(check(&json!(null), &json!("string")).is_err());
+        assert!(check(&json!(1), &json!("string?")).is_err());
+    }
+
+    #[test]
+    fn other_strings_and_values_must_match_exactly() {
+        assert!(check(&json!("success"), &json!("success")).is_ok());
+        assert_eq!(
+            check(&json!("failure"), &json!("success")),
+            Err("expected success at result, found \"failure\"".to_string())
+        );
+        assert!(check(&json!(7), &json!(7)).is_ok());
+        assert!(check(&json!(8), &json!(7)).is_err());
+    }
+
+    #[test]
+    fn checks_required_fields_of_objects() {
+        let shape = json!({ "received": "integer", "rx_unit": "string?" });
+        assert!(check(
+            &json!({ "received": 10, "rx_unit": null, "extra": 1 }),
+            &shape
+        )
+        .is_ok());
+        assert_eq!(
+            check(&json!({ "received": 10 }), &shape),
+            Err("missing field result.rx_unit".to_string())
+        );
+        assert_eq!(
+            check(&json!({ "received": "10", "rx_unit": null }), &shape),
+            Err("expected integer at result.received, found \"10\"".to_string())
+        );
+        assert!(check(&json!([]), &shape).is_err());
+    }
+
+    #[test]
+    fn checks_every_element_of_arrays() {
+        let shape = json!([{ "ssid": "string" }]);
+        assert!(check(&json!([]), &shape).is_ok());
+        assert!(check(&json!([{ "ssid": "a" }, { "ssid": "b" }]), &shape).is_ok());
+        assert_eq!(
+            check(&json!([{ "ssid": "a" }, { "ssid": 2 }]), &shape),
+            Err("expected string at result[1].ssid, found 2".to_string())
+        );
+        // an empty array shape only checks that the value is an array
+        assert!(check(&json!([1, "a"]), &json!([])).is_ok());
+        assert!(check(&json!({}), &json!([])).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Status of systemd units, read from the properties shown by `systemctl show`.
+//!
+//! Unlike the exit code of `systemctl status`, these properties distinguish a unit which is not installed
+//! from one which failed, is stopped, or is being restarted after crashing.
+
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::Serialize;
+
+use crate::error::ProbeError;
+
+/// properties of a unit which are read by unit_status
+const PROPERTIES: &str =
+    "LoadState,ActiveState,SubState,MainPID,NRestarts,ExecMainStartTimestampMonotonic";
+
+/// UnitStatus is the state of a systemd unit
+#[derive(Debug, Clone, Serialize)]
+pub struct UnitStatus {
+    // whether the unit file was found and loaded, e.g. loaded, not-found or masked
+    pub load_state: String,
+    // high-level state of the unit, e.g. active, inactive, activating or failed
+    pub active_state: String,
+    // state specific to the type of unit, e.g. running, dead, exited or auto-restart
+    pub sub_state: String,
+    // pid of the main process of the unit, if it has one
+    pub main_pid: Option<u32>,
+    // number of times systemd has restarted the unit since it was last started manually,
+    // which is not known on versions of systemd older than 235
+    pub restarts: Option<u32>,
+    // unix time in seconds at which the main process was last started, if it has been started since boot
+    pub main_start_timestamp: Option<u64>,
+}
+
+impl UnitStatus {
+    /// returns true if the unit is running, which is the case when `systemctl status` exits with 0
+    pub fn is_running(&self) -> bool {
+        matches!(self.active_state.as_str(), "active" | "reloading")
+    }
+
+    /// returns true if there is no unit file for the unit, which usually means its package is not installed
+    pub fn is_not_found(&self) -> bool {
+        self.load_state == "not-found"
+    }
+
+    /// get a human-readable description of the state of the unit
+    pub fn describe(&self) -> String {
+        let restarts = match self.restarts {
+            Some(restarts) if restarts > 0 => format!(", restarted {} times", restarts),
+            _ => String::new(),
+        };
+        match (
+            self.load_state.as_str(),
+            self.active_state.as_str(),
+            self.sub_state.as_str(),
+        ) {
+            ("not-found", _, _) => "unit not found".to_string(),
+            ("masked", _, _) => "unit is masked".to_string(),
+            (_, "activating", "auto-restart") => {
+                format!("waiting to be restarted after exiting{}", restarts)
+            }
+            (_, "failed", _) => format!("failed{}", restarts),
+            (_, "inactive", _) => "stopped".to_string(),
+            (_, active_state, sub_state) => format!("{} ({}){}", active_state, sub_state, restarts),
+        }
+    }
+}
+
+/// get the status of a systemd unit
+pub fn unit_status(unit: &str) -> Result<UnitStatus, ProbeError> {
+    let output = Command::new("/usr/bin/systemctl")
+        .arg("show")
+        .arg(format!("--property={}", PROPERTIES))
+        .arg(unit)
+        .output()?;
+    if !output.status.success() {
+        return Err(ProbeError::ProcessError(format!(
+            "systemctl show {} failed: {}",
+            unit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let show_output = String::from_utf8(output.stdout)?;
+    parse_unit_status(&show_output, boot_time)
+}
+
+/// helper function which parses the properties shown by `systemctl show`,
+/// calling boot_time only if it is needed to find the time the main process was started
+fn parse_unit_status<F>(show_output: &str, boot_time: F) -> Result<UnitStatus, ProbeError>
+where
+    F: FnOnce() -> Result<SystemTime, ProbeError>,
+{
+    // each property is shown on its own line, as Name=value
+    let properties: HashMap<&str, &str> = show_output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            Some((parts.next()?, parts.next()?.trim()))
+        })
+        .collect();
+    let property = |name: &str| properties.get(name).copied().unwrap_or_default();
+    // systemd shows 0 for a pid or timestamp which is not set
+    let number = |name: &str| property(name).parse::<u64>().ok().filter(|n| *n != 0);
+
+    let main_start_timestamp = match number("ExecMainStartTimestampMonotonic") {
+        Some(since_boot) => {
+            let started = boot_time()? + Duration::from_micros(since_boot);
+            started
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|time| time.as_secs())
+        }
+        None => None,
+    };
+    Ok(UnitStatus {
+        load_state: property("LoadState").to_string(),
+        active_state: property("ActiveState").to_string(),
+        sub_state: property("SubState").to_string(),
+        main_pid: number("MainPID").map(|pid| pid as u32),
+        restarts: property("NRestarts").parse().ok(),
+        main_start_timestamp,
+    })
+}
+
+/// helper function which gets the time at which the system booted, from /proc/stat
+fn boot_time() -> Result<SystemTime, ProbeError> {
+    let stat = fs::read_to_string("/proc/stat")?;
+    parse_boot_time(&stat)
+        .ok_or_else(|| ProbeError::ProcessError("boot time not found in /proc/stat".to_string()))
+}
+
+/// helper function which parses the boot time from the btime line of /proc/stat
+fn parse_boot_time(stat: &str) -> Option<SystemTime> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+        .map(|btime| UNIX_EPOCH + Duration::from_secs(btime))
+}
+
+/// get the number of times systemd scheduled an automatic restart of a unit since the given time,
+/// from the messages it logged to the journal
+pub fn restarts_since(unit: &str, since: SystemTime) -> Result<u32, ProbeError> {
+    let since = since
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let output = Command::new("/usr/bin/journalctl")
+        .arg("-u")
+        .arg(unit)
+        .arg(format!("--since=@{}", since))
+        .arg("-o")
+        .arg("cat")
+        .arg("--no-pager")
+        .output()?;
+    let journal_output = String::from_utf8(output.stdout)?;
+    Ok(count_restarts(&journal_output))
+}
+
+/// helper function which counts the automatic restarts logged by systemd in the output of journalctl
+fn count_restarts(journal_output: &str) -> u32 {
+    // systemd logs e.g. "peach-web.service: Scheduled restart job, restart counter is at 7."
+    journal_output
+        .lines()
+        .filter(|line| line.contains("Scheduled restart job"))
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// boot time used when parsing canned output, in unix seconds
+    const BOOT_TIME: u64 = 1_600_000_000;
+
+    /// helper function which parses canned `systemctl show` output, booted at BOOT_TIME
+    fn parse(show_output: &str) -> UnitStatus {
+        parse_unit_status(show_output, || {
+            Ok(UNIX_EPOCH + Duration::from_secs(BOOT_TIME))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_running_unit() {
+        let status = parse(
+            "LoadState=loaded\nActiveState=active\nSubState=running\nMainPID=1234\nNRestarts=2\nExecMainStartTimestampMonotonic=5000000\n",
+        );
+        assert!(status.is_running());
+        assert!(!status.is_not_found());
+        assert_eq!(status.main_pid, Some(1234));
+        assert_eq!(status.restarts, Some(2));
+        assert_eq!(status.main_start_timestamp, Some(BOOT_TIME + 5));
+        assert_eq!(status.describe(), "active (running), restarted 2 times");
+    }
+
+    #[test]
+    fn parses_unit_which_was_not_found() {
+        // systemd shows properties which are not set as 0, and omits NRestarts before version 235
+        let status = parse(
+            "LoadState=not-found\nActiveState=inactive\nSubState=dead\nMainPID=0\nExecMainStartTimestampMonotonic=0\n",
+        );
+        assert!(!status.is_running());
+        assert!(status.is_not_found());
+        assert_eq!(status.main_pid, None);
+        assert_eq!(status.restarts, None);
+        assert_eq!(status.main_start_timestamp, None);
+        assert_eq!(status.describe(), "unit not found");
+    }
+
+    #[test]
+    fn parses_unit_waiting_to_restart() {
+        let status = parse(
+            "LoadState=loaded\nActiveState=activating\nSubState=auto-restart\nMainPID=0\nNRestarts=7\nExecMainStartTimestampMonotonic=0\n",
+        );
+        assert!(!status.is_running());
+        assert_eq!(
+            status.describe(),
+            "waiting to be restarted after exiting, restarted 7 times"
+        );
+    }
+
+    #[test]
+    fn boot_time_is_only_read_when_needed() {
+        let status = parse_unit_status("LoadState=loaded\nActiveState=failed\n", || {
+            Err(ProbeError::ProcessError("no boot time".to_string()))
+        });
+        assert_eq!(status.unwrap().describe(), "failed");
+        let status = parse_unit_status("ExecMainStartTimestampMonotonic=1\n", || {
+            Err(ProbeError::ProcessError("no boot time".to_string()))
+        });
+        assert!(status.is_err());
+    }
+
+    #[test]
+    fn counts_restarts_in_journal() {
+        let journal = "Started peach-web.\n\
+            peach-web.service: Main process exited, code=exited, status=1/FAILURE\n\
+            peach-web.service: Failed with result 'exit-code'.\n\
+            peach-web.service: Scheduled restart job, restart counter is at 1.\n\
+            Stopped peach-web.\n\
+            Started peach-web.\n\
+            peach-web.service: Scheduled restart job, restart counter is at 2.\n";
+        assert_eq!(count_restarts(journal), 2);
+        assert_eq!(count_restarts("-- No entries --\n"), 0);
+    }
+
+    #[test]
+    fn parses_boot_time() {
+        let stat = "cpu  10132153 290696 3084719 46828483 16683 0 25195 0 0 0\nintr 1462898\nctxt 3421210\nbtime 1600000000\nprocesses 26442\n";
+        assert_eq!(
+            parse_boot_time(stat),
+            Some(UNIX_EPOCH + Duration::from_secs(BOOT_TIME))
+        );
+        assert_eq!(parse_boot_time("cpu  1 2 3\nctxt 4\n"), None);
+        assert_eq!(parse_boot_time("btime soon\n"), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Running endpoint calls with a deadline, so that a hung microservice cannot stall the whole probe.
+//!
+//! Calls are made on a separate thread. If a call does not return before its deadline, the thread
+//! is left to finish (or hang) in the background and the call is reported as timed out. Calls whose
+//! effects matter after they time out, such as calls which change the state of a service, can be counted
+//! with InFlight, so that the probe can wait for them to finish.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::ProbeError;
+
+/// call the given function, giving up if it has not returned within timeout
+/// if timeout is None, the function is called on the current thread and may block forever
+pub fn run_with_timeout<T, E, F>(call: F, timeout: Option<Duration>) -> Result<T, ProbeError>
+where
+    T: Send + 'static,
+    E: Into<ProbeError>,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return call().map_err(Into::into),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // the receiver is dropped if the call timed out, in which case the result is discarded
+        let _ = sender.send(call().map_err(Into::into));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(ProbeError::Timeout {
+            timeout_ms: timeout.as_millis() as u64,
+        }),
+        // the sender is only dropped without sending if the call panicked
+        Err(RecvTimeoutError::Disconnected) => Err(ProbeError::UnexpectedResponse(
+            "endpoint call panicked".to_string(),
+        )),
+    }
+}
+
+/// InFlight counts the calls which were started and have not finished yet,
+/// including calls which timed out but are still running in the background
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    calls: Arc<(Mutex<usize>, Condvar)>,
+}
+
+/// InFlightCall is a call counted by InFlight, which is counted as finished when it is dropped
+#[derive(Debug)]
+pub struct InFlightCall {
+    calls: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl InFlight {
+    /// count a call as started, until the returned InFlightCall is dropped
+    pub fn start(&self) -> InFlightCall {
+        let (count, _) = &*self.calls;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        InFlightCall {
+            calls: self.calls.clone(),
+        }
+    }
+
+    /// wrap a call so that it is counted as in flight until it has returned, or until it is dropped without being called
+    pub fn track<T, E, F>(&self, call: F) -> impl FnOnce() -> Result<T, E> + Send + 'static
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        let in_flight = self.start();
+        move || {
+            let result = call();
+            drop(in_flight);
+            result
+        }
+    }
+
+    /// get the number of calls which have not finished
+    pub fn count(&self) -> usize {
+        let (count, _) = &*self.calls;
+        *count.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// wait for all calls to finish, giving up after timeout
+    /// returns true if all calls have finished
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (count, finished) = &*self.calls;
+        let count = count.lock().unwrap_or_else(|e| e.into_inner());
+        let (count, _) = finished
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .unwrap_or_else(|e| e.into_inner());
+        *count == 0
+    }
+}
+
+impl Drop for InFlightCall {
+    fn drop(&mut self) {
+        let (count, finished) = &*self.calls;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        finished.notify_all();
+    }
+}
@@ -0,0 +1,263 @@
+//! Validators for the results returned by peach-lib clients.
+//!
+//! Each validator checks the json representation of a result for the expected types and
+//! required fields (using shape.rs), and then for invariants such as ranges, returning a
+//! description of the first violation found.
+
+use std::net::IpAddr;
+
+use serde_json::{json, Value};
+
+use crate::shape;
+
+/// Validator checks the result of an endpoint, returning a description of any violation
+pub type Validator = fn(&Value) -> Result<(), String>;
+
+/// helper function which checks that the given fields of a json object are within a range
+fn check_range(value: &Value, fields: &[&str], min: f64, max: f64) -> Result<(), String> {
+    for field in fields {
+        let number = value[field].as_f64().unwrap_or(f64::NAN);
+        if !(min..=max).contains(&number) {
+            return Err(format!(
+                "{} should be between {} and {}, found {}",
+                field, min, max, value[field]
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// helper function which checks that a string contains a json array
+fn check_json_array(value: &str) -> Result<Vec<Value>, String> {
+    match serde_json::from_str(value) {
+        Ok(Value::Array(elements)) => Ok(elements),
+        _ => Err(format!("expected a json encoded array, found {}", value)),
+    }
+}
+
+/// ping endpoints return "success"
+pub fn ping(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("success"))
+}
+
+/// cpu usage is reported as a percentage of time spent in each state
+pub fn cpu_stats_percent(value: &Value) -> Result<(), String> {
+    let fields = ["user", "system", "idle", "nice"];
+    shape::check(
+        value,
+        &json!({ "user": "number", "system": "number", "idle": "number", "nice": "number" }),
+    )?;
+    check_range(value, &fields, 0.0, 100.0)?;
+    // allow a little slack for rounding
+    let total: f64 = fields
+        .iter()
+        .filter_map(|field| value[field].as_f64())
+        .sum();
+    if total > 100.5 {
+        return Err(format!(
+            "cpu percentages should sum to at most 100, found {}",
+            total
+        ));
+    }
+    Ok(())
+}
+
+/// load averages can never be negative
+pub fn load_average(value: &Value) -> Result<(), String> {
+    shape::check(
+        value,
+        &json!({ "one": "number", "five": "number", "fifteen": "number" }),
+    )?;
+    check_range(value, &["one", "five", "fifteen"], 0.0, f64::MAX)
+}
+
+/// memory stats are given in kilobytes, and used and free memory cannot exceed the total
+pub fn mem_stats(value: &Value) -> Result<(), String> {
+    shape::check(
+        value,
+        &json!({ "total": "integer", "free": "integer", "used": "integer" }),
+    )?;
+    let total = value["total"].as_u64().unwrap_or_default();
+    if total == 0 {
+        return Err("total memory should be greater than 0".to_string());
+    }
+    for field in &["free", "used"] {
+        if value[field].as_u64().unwrap_or_default() > total {
+            return Err(format!("{} memory should not exceed total memory", field));
+        }
+    }
+    Ok(())
+}
+
+/// disk usage is a json encoded array of filesystems, which should include at least the root filesystem
+pub fn disk_usage(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string"))?;
+    let filesystems = check_json_array(value.as_str().unwrap_or_default())?;
+    if filesystems.is_empty() {
+        return Err("disk usage should include at least one filesystem".to_string());
+    }
+    for filesystem in &filesystems {
+        if filesystem.get("used_percentage").is_some() {
+            check_range(filesystem, &["used_percentage"], 0.0, 100.0)?;
+        }
+    }
+    Ok(())
+}
+
+/// uptime is a non-negative number encoded as a string
+pub fn uptime(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string"))?;
+    match value.as_str().unwrap_or_default().trim().parse::<f64>() {
+        Ok(uptime) if uptime >= 0.0 => Ok(()),
+        _ => Err(format!(
+            "uptime should be a non-negative number, found {}",
+            value
+        )),
+    }
+}
+
+/// ip addresses must parse as ipv4 or ipv6 addresses
+pub fn ip(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string"))?;
+    match value.as_str().unwrap_or_default().parse::<IpAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("expected an ip address, found {}", value)),
+    }
+}
+
+/// ssids are between 1 and 32 bytes long
+pub fn ssid(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string"))?;
+    let length = value.as_str().unwrap_or_default().len();
+    if (1..=32).contains(&length) {
+        Ok(())
+    } else {
+        Err(format!(
+            "ssid should be 1 to 32 bytes long, found {}",
+            value
+        ))
+    }
+}
+
+/// interface state is one of the operstate values reported by the kernel
+pub fn state(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string"))?;
+    let states = [
+        "up",
+        "down",
+        "dormant",
+        "unknown",
+        "lowerlayerdown",
+        "notpresent",
+        "testing",
+    ];
+    if states.contains(&value.as_str().unwrap_or_default()) {
+        Ok(())
+    } else {
+        Err(format!("expected an interface state, found {}", value))
+    }
+}
+
+/// traffic counters are non-negative integers
+pub fn traffic(value: &Value) -> Result<(), String> {
+    shape::check(
+        value,
+        &json!({ "received": "integer", "transmitted": "integer" }),
+    )?;
+    check_range(value, &["received", "transmitted"], 0.0, f64::MAX)
+}
+
+/// lists of networks are either missing, or a json encoded array
+pub fn networks(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string?"))?;
+    match value.as_str() {
+        Some(networks) => check_json_array(networks).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// network ids are either missing, or a non-negative integer encoded as a string
+pub fn network_id(value: &Value) -> Result<(), String> {
+    shape::check(value, &json!("string?"))?;
+    match value.as_str() {
+        Some(id) if id.trim().parse::<u32>().is_err() => {
+            Err(format!("expected a network id, found {}", value))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_cpu_stats_percent() {
+        let valid = json!({ "user": 4.2, "system": 1.3, "idle": 94.5, "nice": 0.0 });
+        assert!(cpu_stats_percent(&valid).is_ok());
+        let negative = json!({ "user": -1.0, "system": 1.3, "idle": 94.5, "nice": 0.0 });
+        assert!(cpu_stats_percent(&negative).is_err());
+        let over_total = json!({ "user": 60.0, "system": 30.0, "idle": 20.0, "nice": 0.0 });
+        assert_eq!(
+            cpu_stats_percent(&over_total),
+            Err("cpu percentages should sum to at most 100, found 110".to_string())
+        );
+        assert!(cpu_stats_percent(&json!({ "user": 4.2 })).is_err());
+    }
+
+    #[test]
+    fn validates_load_average() {
+        assert!(load_average(&json!({ "one": 0.5, "five": 0.3, "fifteen": 0.1 })).is_ok());
+        assert!(load_average(&json!({ "one": -0.5, "five": 0.3, "fifteen": 0.1 })).is_err());
+    }
+
+    #[test]
+    fn validates_mem_stats() {
+        assert!(mem_stats(&json!({ "total": 1024, "free": 512, "used": 512 })).is_ok());
+        assert!(mem_stats(&json!({ "total": 0, "free": 0, "used": 0 })).is_err());
+        assert_eq!(
+            mem_stats(&json!({ "total": 1024, "free": 512, "used": 2048 })),
+            Err("used memory should not exceed total memory".to_string())
+        );
+    }
+
+    #[test]
+    fn validates_disk_usage() {
+        assert!(disk_usage(&json!(
+            "[{\"filesystem\":\"/dev/root\",\"used_percentage\":42}]"
+        ))
+        .is_ok());
+        assert!(disk_usage(&json!("[]")).is_err());
+        assert!(disk_usage(&json!("[{\"used_percentage\":142}]")).is_err());
+        assert!(disk_usage(&json!("not json")).is_err());
+    }
+
+    #[test]
+    fn validates_uptime() {
+        assert!(uptime(&json!("3600")).is_ok());
+        assert!(uptime(&json!("-1")).is_err());
+        assert!(uptime(&json!(3600)).is_err());
+    }
+
+    #[test]
+    fn validates_network_values() {
+        assert!(ip(&json!("192.168.1.10")).is_ok());
+        assert!(ip(&json!("fe80::1")).is_ok());
+        assert!(ip(&json!("x.x.x.x")).is_err());
+        assert!(ssid(&json!("peach")).is_ok());
+        assert!(ssid(&json!("")).is_err());
+        assert!(ssid(&json!("a".repeat(33))).is_err());
+        assert!(state(&json!("up")).is_ok());
+        assert!(state(&json!("sideways")).is_err());
+        assert!(traffic(&json!({ "received": 10, "transmitted": 20 })).is_ok());
+        assert!(traffic(&json!({ "received": -10, "transmitted": 20 })).is_err());
+        assert!(networks(&json!(null)).is_ok());
+        assert!(networks(&json!("[\"peach\"]")).is_ok());
+        assert!(networks(&json!("peach")).is_err());
+        assert!(network_id(&json!(null)).is_ok());
+        assert!(network_id(&json!("0")).is_ok());
+        assert!(network_id(&json!("zero")).is_err());
+        assert!(ping(&json!("success")).is_ok());
+        assert!(ping(&json!("pong")).is_err());
+    }
+}
@@ -1,3 +1,40 @@
+/// address of peach-oled used by peach-lib if the PEACH_OLED_SERVER environment variable is not set
+pub const DEFAULT_OLED_SERVER: &str = "127.0.0.1:5112";
+
+/// address of peach-network used by peach-lib if the PEACH_NETWORK_SERVER environment variable is not set
+pub const DEFAULT_NETWORK_SERVER: &str = "127.0.0.1:5110";
+
+/// address of peach-stats used by peach-lib if the PEACH_STATS_SERVER environment variable is not set
+pub const DEFAULT_STATS_SERVER: &str = "127.0.0.1:5113";
+
+/// dpkg status file from which installed versions are read, if no other path is given on the command line
+pub const DEFAULT_DPKG_STATUS_PATH: &str = "/var/lib/dpkg/status";
+
+/// base url of peach-web if none is given on the command line
+pub const DEFAULT_WEB_URL: &str = "http://127.0.0.1:3000";
+
+/// file in which peach-monitor stores the total network data usage
+pub const MONITOR_DATA_PATH: &str = "/var/lib/peachcloud/net/data.json";
+
+/// file in which the data usage alert thresholds and flags used by peach-monitor are stored
+pub const MONITOR_THRESHOLD_PATH: &str = "/var/lib/peachcloud/net/threshold.json";
+
+/// wpa_supplicant config in which peach-network saves the networks of the device
+pub const WPA_SUPPLICANT_CONFIG_PATH: &str = "/etc/wpa_supplicant/wpa_supplicant-wlan0.conf";
+
+/// routes of peach-web which are probed, as (path, expected status code, expected content type)
+pub const WEB_ROUTES: [(&str, u16, &str); 9] = [
+    ("/", 200, "text/html"),
+    ("/login", 200, "text/html"),
+    ("/network", 200, "text/html"),
+    ("/network/wifi", 200, "text/html"),
+    ("/device", 200, "text/html"),
+    ("/css/peachcloud.css", 200, "text/css"),
+    ("/js/common.js", 200, "javascript"),
+    ("/api/v1/ping", 200, "application/json"),
+    ("/api/v1/network/state", 200, "application/json"),
+];
+
 pub const PEACH_LOGO: [u8; 512] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
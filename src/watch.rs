@@ -0,0 +1,235 @@
+//! Watch mode, in which services are probed repeatedly on an interval.
+//!
+//! A rolling history of results is kept, and only changes in state are printed: a service going
+//! offline or coming back online, starting or stopping crash-looping, errors of peach-probe itself
+//! appearing or clearing, and an endpoint starting to fail or recovering.
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::ServiceConfig;
+use crate::probe::{EndpointOutcome, EndpointResult, PeachProbe, ProbeResult};
+
+/// Watcher stores the rolling history of results of a watched set of services
+pub struct Watcher {
+    // results of the most recent runs, oldest first, with one result per service in each run
+    history: VecDeque<Vec<ProbeResult>>,
+    // maximum number of runs kept in the history
+    history_len: usize,
+    // number of runs which have been completed
+    runs: u64,
+}
+
+impl Watcher {
+    pub fn new(history_len: usize) -> Watcher {
+        Watcher {
+            history: VecDeque::new(),
+            history_len: history_len.max(1),
+            runs: 0,
+        }
+    }
+
+    /// probe the given services every interval, printing changes in their state, forever
+    pub fn run(
+        &mut self,
+        probe: &mut PeachProbe,
+        services: &[ServiceConfig],
+        jobs: usize,
+        interval: Duration,
+    ) -> ! {
+        loop {
+            let start = Instant::now();
+            probe.results.clear();
+            probe.probe_services(services, jobs);
+            let results = std::mem::take(&mut probe.results);
+            self.record(results);
+
+            // wait for the rest of the interval, starting the next run immediately if probing took longer
+            if let Some(remaining) = interval.checked_sub(start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+
+    /// add the results of a run to the history, printing any changes from the previous run
+    fn record(&mut self, results: Vec<ProbeResult>) {
+        self.runs += 1;
+        for result in &results {
+            self.print_transitions(result);
+        }
+        self.history.push_back(results);
+        while self.history.len() > self.history_len {
+            self.history.pop_front();
+        }
+    }
+
+    /// get the results of a service in previous runs, most recent first
+    fn previous<'a>(&'a self, microservice: &'a str) -> impl Iterator<Item = &'a ProbeResult> {
+        self.history.iter().rev().filter_map(move |results| {
+            results
+                .iter()
+                .find(|result| result.microservice == microservice)
+        })
+    }
+
+    /// helper function which gets a description of how many runs a state lasted, according to the history
+    fn duration(&self, microservice: &str, in_state: impl Fn(&ProbeResult) -> bool) -> String {
+        let runs = self
+            .previous(microservice)
+            .take_while(|result| in_state(result))
+            .count();
+        if runs == self.history.len() && runs == self.history_len {
+            format!("at least {} runs", runs)
+        } else {
+            format!("{} runs", runs)
+        }
+    }
+
+    /// prints the changes in state of a service since the previous run
+    fn print_transitions(&self, result: &ProbeResult) {
+        let name = &result.microservice;
+        let prefix = format!("[run {}] {}", self.runs, name);
+        let last = match self.previous(name).next() {
+            Some(last) => last,
+            // on the first run, the initial state of every service is printed
+            None => {
+                if result.is_running {
+                    println!("{} is online", prefix);
+                } else {
+                    println!("{} is offline", prefix);
+                }
+                if result.crash_looping {
+                    println!("{} is crash-looping{}", prefix, restarts(result));
+                }
+                for error in &result.probe_errors {
+                    println!("{} could not be fully probed: {}", prefix, error);
+                }
+                for endpoint in failing_endpoints(result) {
+                    println!(
+                        "{} endpoint {} is failing: {}",
+                        prefix,
+                        endpoint,
+                        endpoint_error(result, endpoint)
+                    );
+                }
+                return;
+            }
+        };
+
+        if last.is_running && !result.is_running {
+            let state = result
+                .unit_status
+                .as_ref()
+                .map(|unit_status| format!(" ({})", unit_status.describe()))
+                .unwrap_or_default();
+            println!(
+                "{} went offline{}, after being online for {}",
+                prefix,
+                state,
+                self.duration(name, |r| r.is_running)
+            );
+            if let Some(service_log) = &result.service_log {
+                println!("{}", service_log);
+            }
+        } else if !last.is_running && result.is_running {
+            println!(
+                "{} came back online, after being offline for {}",
+                prefix,
+                self.duration(name, |r| !r.is_running)
+            );
+        }
+
+        if !last.crash_looping && result.crash_looping {
+            println!(
+                "{} started crash-looping{}, after not crash-looping for {}",
+                prefix,
+                restarts(result),
+                self.duration(name, |r| !r.crash_looping)
+            );
+        } else if last.crash_looping && !result.crash_looping {
+            println!(
+                "{} stopped crash-looping, after crash-looping for {}",
+                prefix,
+                self.duration(name, |r| r.crash_looping)
+            );
+        }
+
+        // errors of peach-probe itself, such as calls from a previous run which have still not returned
+        if last.probe_errors.is_empty() {
+            for error in &result.probe_errors {
+                println!("{} could not be fully probed: {}", prefix, error);
+            }
+        } else if result.probe_errors.is_empty() {
+            println!(
+                "{} can be fully probed again, after {} with errors",
+                prefix,
+                self.duration(name, |r| !r.probe_errors.is_empty())
+            );
+        }
+
+        // endpoints are compared with the most recent run in which they were called, since a service
+        // is not called at all in a run in which its calls from a previous run have not returned
+        let last_called = self
+            .previous(name)
+            .find(|r| !r.endpoints.is_empty())
+            .unwrap_or(last);
+        let last_failing = failing_endpoints(last_called);
+        let failing = failing_endpoints(result);
+        for endpoint in &failing {
+            if !last_failing.contains(endpoint) {
+                println!(
+                    "{} endpoint {} started failing: {}",
+                    prefix,
+                    endpoint,
+                    endpoint_error(result, endpoint)
+                );
+            }
+        }
+        for endpoint in &last_failing {
+            // an endpoint which was not called this run, e.g. because the service is offline, has not recovered
+            let succeeded = matches!(
+                result.endpoint(endpoint),
+                Some(EndpointResult {
+                    outcome: EndpointOutcome::Success,
+                    ..
+                })
+            );
+            if !failing.contains(endpoint) && succeeded {
+                println!(
+                    "{} endpoint {} recovered, after failing for {}",
+                    prefix,
+                    endpoint,
+                    self.duration(name, |r| {
+                        r.endpoints.is_empty() || failing_endpoints(r).contains(endpoint)
+                    })
+                );
+            }
+        }
+    }
+}
+
+/// helper function which gets the names of all endpoints which failed, returned invalid responses or timed out
+fn failing_endpoints(result: &ProbeResult) -> Vec<&str> {
+    result
+        .failing_endpoints()
+        .map(|endpoint| endpoint.endpoint.as_str())
+        .collect()
+}
+
+/// helper function which gets a description of how many times a service was restarted within the crash-loop window
+fn restarts(result: &ProbeResult) -> String {
+    match result.recent_restarts {
+        Some(restarts) => format!(" (restarted {} times recently)", restarts),
+        None => String::new(),
+    }
+}
+
+/// helper function which gets the error returned by an endpoint
+fn endpoint_error<'a>(result: &'a ProbeResult, endpoint: &str) -> &'a str {
+    result
+        .endpoint(endpoint)
+        .and_then(|endpoint| endpoint.error.as_ref())
+        .map(|error| error.message.as_str())
+        .unwrap_or_default()
+}